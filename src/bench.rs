@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+use crate::bvh::Bvh;
+use crate::framebuffer::Framebuffer;
+use crate::march::Strategy;
+use crate::renderer::{PacketLayout, Renderer};
+use crate::world::{construct_sphere_field, Union};
+
+const FRAMES: usize = 20;
+// Spheres in the scene comparing a plain union with a BVH
+const FIELD: usize = 1000;
+
+// Renders the default scene without opening a window, and prints how long a
+// frame takes with each of the options being compared
+pub fn run(mut renderer: Renderer, width: usize, height: usize) {
+    let mut data = vec![0; 4 * width * height];
+
+    println!("{}x{}, average of {} frames", width, height, FRAMES);
+
+    let mut layouts = Vec::new();
+    for &layout in &[PacketLayout::Row, PacketLayout::Block] {
+        renderer.set_layout(layout);
+        let ms = frame_time(&renderer, &mut data, width, height, FRAMES);
+        println!("{:?} packets: {:.2} ms", layout, ms);
+        layouts.push(ms);
+    }
+    println!("Block packets are {:.2}x as fast as rows", layouts[0] / layouts[1]);
+
+    for &compact in &[false, true] {
+        renderer.set_compact(compact);
+        let ms = frame_time(&renderer, &mut data, width, height, FRAMES);
+        println!("Lane compaction {}: {:.2} ms", if compact { "on" } else { "off" }, ms);
+    }
+
+    let chosen = renderer.march_settings_mut().strategy;
+    for &strategy in &[Strategy::Sphere, Strategy::over_relaxed(), Strategy::enhanced()] {
+        renderer.march_settings_mut().strategy = strategy;
+        let ms = frame_time(&renderer, &mut data, width, height, FRAMES);
+        println!("{:?}: {:.2} ms", strategy, ms);
+    }
+    renderer.march_settings_mut().strategy = chosen;
+
+    for &prepass in &[false, true] {
+        renderer.set_prepass(prepass);
+        let ms = frame_time(&renderer, &mut data, width, height, FRAMES);
+        println!("Cone pre-pass {}: {:.2} ms", if prepass { "on" } else { "off" }, ms);
+    }
+
+    renderer.set_world(Box::new(Bvh::flat(construct_sphere_field(FIELD))));
+    let ms = frame_time(&renderer, &mut data, width, height, FRAMES);
+    println!("Flat union of {} spheres: {:.2} ms", FIELD, ms);
+
+    renderer.set_world(Box::new(Union::new(construct_sphere_field(FIELD))));
+    let ms = frame_time(&renderer, &mut data, width, height, FRAMES);
+    println!("BVH union of {} spheres: {:.2} ms", FIELD, ms);
+}
+
+// Milliseconds per frame
+fn frame_time(renderer: &Renderer, data: &mut [u8], width: usize, height: usize, frames: usize) -> f64 {
+    // Once first so rayon's threads are running
+    renderer.render_into(&mut Framebuffer::new(data, width, height));
+
+    let start = Instant::now();
+    for _ in 0..frames {
+        renderer.render_into(&mut Framebuffer::new(data, width, height));
+    }
+
+    start.elapsed().as_secs_f64() * 1000. / frames as f64
+}
@@ -0,0 +1,282 @@
+use crate::vec::Vec3dx16;
+use crate::world::{Bounds, World};
+use crate::simd::f32x16;
+
+// Objects per leaf, below this splitting costs more than it saves
+const LEAF_SIZE: usize = 4;
+// Deeper than a median split of any scene that fits in memory. Traversal
+// keeps at most one node per level on its stack.
+const MAX_DEPTH: usize = 64;
+
+enum Node {
+    // A range of `objects`
+    Leaf { bounds: Bounds, start: usize, end: usize },
+    // Indices into `nodes`
+    Branch { bounds: Bounds, left: usize, right: usize },
+}
+
+impl Node {
+    fn bounds(&self) -> &Bounds {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Branch { bounds, .. } => bounds,
+        }
+    }
+}
+
+// A union of objects with a bounding volume hierarchy over them, so that for
+// each packet only the objects whose boxes are closer than the nearest
+// surface found so far are evaluated. Objects without finite bounds, like
+// planes, are evaluated for every packet. Scenes use it through `Union`.
+// Objects whose distance underestimates could have given less than the ones
+// that are evaluated, so the result can be more than the smallest distance of
+// all objects, but never more than the distance to the nearest surface.
+pub struct Bvh {
+    objects: Vec<Box<dyn World>>,
+    nodes: Vec<Node>,
+    unbounded: Vec<Box<dyn World>>,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Box<dyn World>>) -> Bvh {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for obj in objects {
+            match obj.bounds() {
+                Some(bounds) if bounds.is_finite() => bounded.push((bounds, obj)),
+                _ => unbounded.push(obj),
+            }
+        }
+
+        let mut bvh = Bvh {
+            objects: Vec::with_capacity(bounded.len()),
+            nodes: Vec::new(),
+            unbounded,
+        };
+        if !bounded.is_empty() {
+            bvh.build(bounded, 1);
+        }
+        bvh
+    }
+
+    // Every object evaluated for every packet, like a union without a
+    // hierarchy, to compare against
+    pub fn flat(objects: Vec<Box<dyn World>>) -> Bvh {
+        Bvh {
+            objects: Vec::new(),
+            nodes: Vec::new(),
+            unbounded: objects,
+        }
+    }
+
+    // Adds the subtree over `items`, at `depth` counting from 1 at the root,
+    // and returns its index. Splits at the median along the axis where the
+    // centers are the most spread out.
+    fn build(&mut self, mut items: Vec<(Bounds, Box<dyn World>)>, depth: usize) -> usize {
+        assert!(depth <= MAX_DEPTH, "BVH is too deep to traverse");
+
+        let bounds = items[1..].iter().fold(items[0].0, |acc, (b, _)| acc.union(b));
+        let index = self.nodes.len();
+
+        if items.len() <= LEAF_SIZE {
+            let start = self.objects.len();
+            self.objects.extend(items.into_iter().map(|(_, obj)| obj));
+            self.nodes.push(Node::Leaf { bounds, start, end: self.objects.len() });
+            return index;
+        }
+
+        let centers: Vec<_> = items.iter().map(|(b, _)| b.center()).collect();
+        let spread = Bounds::from_points(&centers);
+        let size = (
+            spread.max.0 - spread.min.0,
+            spread.max.1 - spread.min.1,
+            spread.max.2 - spread.min.2,
+        );
+        let key = |b: &Bounds| {
+            let c = b.center();
+            if size.0 >= size.1 && size.0 >= size.2 {
+                c.0
+            } else if size.1 >= size.2 {
+                c.1
+            } else {
+                c.2
+            }
+        };
+        items.sort_by(|(a, _), (b, _)| key(a).total_cmp(&key(b)));
+
+        let rest = items.split_off(items.len() / 2);
+
+        // Placeholder until the children have their indices
+        self.nodes.push(Node::Leaf { bounds, start: 0, end: 0 });
+        let left = self.build(items, depth + 1);
+        let right = self.build(rest, depth + 1);
+        self.nodes[index] = Node::Branch { bounds, left, right };
+        index
+    }
+
+    // Calls `visit` with every object that could be closer than the current
+    // best distance for some lane, nearest boxes first. `visit` gets the best
+    // distance so far and returns the new one.
+    fn traverse(
+        &self,
+        x: &Vec3dx16,
+        mut best: f32x16,
+        mut visit: impl FnMut(&dyn World, f32x16) -> f32x16,
+    ) -> f32x16 {
+        for obj in &self.unbounded {
+            best = visit(&**obj, best);
+        }
+        if self.nodes.is_empty() {
+            return best;
+        }
+
+        let mut stack = [(0, f32x16::splat(0.)); MAX_DEPTH];
+        stack[0] = (0, self.nodes[0].bounds().distance(x));
+        let mut len = 1;
+
+        while len > 0 {
+            len -= 1;
+            let (index, distance) = stack[len];
+            // The best distance may have shrunk since this was pushed
+            if distance.lt(best).none() {
+                continue;
+            }
+
+            match self.nodes[index] {
+                Node::Leaf { start, end, .. } => {
+                    for obj in &self.objects[start..end] {
+                        best = visit(&**obj, best);
+                    }
+                }
+                Node::Branch { left, right, .. } => {
+                    let left = (left, self.nodes[left].bounds().distance(x));
+                    let right = (right, self.nodes[right].bounds().distance(x));
+                    let (near, far) = if left.1.sum() <= right.1.sum() {
+                        (left, right)
+                    } else {
+                        (right, left)
+                    };
+
+                    // Branches are at most MAX_DEPTH - 1 deep, and every
+                    // level above this one has at most one node waiting
+                    debug_assert!(len + 2 <= MAX_DEPTH, "BVH traversal stack overflowed");
+                    if far.1.lt(best).any() {
+                        stack[len] = far;
+                        len += 1;
+                    }
+                    if near.1.lt(best).any() {
+                        stack[len] = near;
+                        len += 1;
+                    }
+                }
+            }
+        }
+
+        best
+    }
+}
+
+impl World for Bvh {
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.traverse(x, f32x16::splat(f32::INFINITY), |obj, best| {
+            best.min(obj.distance_estimator(x, time))
+        })
+    }
+
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.distance_color(x, time).1
+    }
+
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let mut colors = Vec3dx16::from_tuple((0., 1., 0.));
+
+        let distances = self.traverse(x, f32x16::splat(f32::INFINITY), |obj, best| {
+            let (distances, colors_) = obj.distance_color(x, time);
+            let closer = distances.lt(best);
+
+            colors.xs = closer.select(colors_.xs, colors.xs);
+            colors.ys = closer.select(colors_.ys, colors.ys);
+            colors.zs = closer.select(colors_.zs, colors.zs);
+
+            best.min(distances)
+        });
+        (distances, colors)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(|node| *node.bounds())
+    }
+
+    fn animated(&self) -> bool {
+        self.objects.iter().chain(&self.unbounded).any(|obj| obj.animated())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::tests::random_points;
+    use crate::world::{construct_sphere, construct_sphere_field, Coloring, Elongate, Plane, Scale, Translation, UnitSphere};
+
+    fn field() -> Vec<Box<dyn World>> {
+        let mut objects = construct_sphere_field(300);
+        objects.push(Box::new(Plane { height: -10. }));
+        objects
+    }
+
+    // Objects whose boxes overlap a lot, some inside others, and long
+    // capsules along each axis that cross any plane the objects could be
+    // split at. All of them have exact distances.
+    fn tangle() -> Vec<Box<dyn World>> {
+        let mut objects: Vec<Box<dyn World>> = Vec::new();
+        for i in 0..40 {
+            let t = i as f32;
+            let at = ((t * 2.3).sin() * 5., (t * 1.7).cos() * 5., 14. + (t * 0.9).sin() * 5.);
+            let sphere = construct_sphere(at, 1. + (t * 0.5).cos().abs() * 2.);
+            objects.push(Box::new(Coloring::new(sphere, (t / 40., 0., 0.))));
+        }
+        for i in 0..4 {
+            let r = 1. + i as f32;
+            let sphere = construct_sphere((3. + 0.2 * r, -2., 12.), r);
+            objects.push(Box::new(Coloring::new(sphere, (0., r / 4., 0.))));
+        }
+        for (i, &along) in [(15., 0., 0.), (0., 15., 0.), (0., 0., 15.)].iter().enumerate() {
+            for &at in &[(1., -1., 14.), (-2., 2., 15.)] {
+                let capsule = Elongate::new(Scale::new(UnitSphere, (0.3, 0.3, 0.3)), along);
+                let capsule = Translation::new(capsule, at);
+                objects.push(Box::new(Coloring::new(capsule, (1., i as f32 / 3., 0.5))));
+            }
+        }
+        objects
+    }
+
+    // The BVH only skips objects that can't be the closest, so with exact
+    // distances it gives the same distances and colors as evaluating every
+    // object anywhere
+    fn assert_matches_flat(objects: fn() -> Vec<Box<dyn World>>) {
+        let bvh = Bvh::new(objects());
+        let flat = Bvh::flat(objects());
+        let region = Bounds::new((-15., -15., -1.), (15., 15., 29.));
+        let time = f32x16::splat(0.);
+
+        for seed in 0..200 {
+            let x = random_points(&region, seed);
+            assert_eq!(bvh.distance_estimator(&x, time), flat.distance_estimator(&x, time));
+            assert_eq!(bvh.distance_color(&x, time), flat.distance_color(&x, time));
+        }
+    }
+
+    #[test]
+    fn matches_flat() {
+        assert_matches_flat(field);
+        assert_matches_flat(tangle);
+    }
+
+    #[test]
+    fn bounds_skip_unbounded_objects() {
+        assert!(Bvh::new(construct_sphere_field(10)).bounds().is_some());
+        assert!(Bvh::new(field()).bounds().is_none());
+    }
+}
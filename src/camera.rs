@@ -0,0 +1,427 @@
+use crate::simd::{f32x16, m32x16};
+
+use std::f32::consts::PI;
+
+use crate::math::floor;
+use crate::vec::Vec3dx16;
+use crate::world::norm;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    // Pinhole camera, straight lines stay straight
+    Perspective,
+    // Parallel rays, `height` is how much of the world fits vertically
+    Orthographic { height: f32 },
+    // Equidistant fisheye, the angle from the center of the image grows
+    // linearly with the distance from it
+    Fisheye,
+    // 360 by 180 degree panorama, ignores the field of view
+    Equirectangular,
+    // Six 90 degree faces in a 3 by 2 grid. Top row is +x, -x, +y and
+    // bottom row is -y, +z, -z, all relative to the camera. Images that
+    // aren't 3:2 are letterboxed.
+    Cubemap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoMode {
+    Mono,
+    // Left eye in the left half of the image, right eye in the right half
+    SideBySide,
+    // Red from the left eye, green and blue from the right
+    Anaglyph,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stereo {
+    pub mode: StereoMode,
+    // Distance between the eyes
+    pub eye_separation: f32,
+    // Distance at which the eyes' views cross, things there appear to be
+    // at the depth of the screen
+    pub convergence: f32,
+}
+
+// Right, up and forward
+pub type Basis = ((f32, f32, f32), (f32, f32, f32), (f32, f32, f32));
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub position: (f32, f32, f32),
+    // Yaw turns around the y axis, with 0 looking along +z and positive
+    // turning towards +x. Pitch is the angle above the horizon.
+    pub yaw: f32,
+    pub pitch: f32,
+    // Full vertical field of view, in radians
+    pub vfov: f32,
+    // Width divided by height
+    pub aspect: f32,
+    // Rays start this far in front of the camera, and give up this far away
+    pub near: f32,
+    pub far: f32,
+    pub projection: Projection,
+    // Radius of the lens, 0 gives a pinhole camera with everything in focus
+    pub aperture: f32,
+    // Distance to the plane that is in focus. Projections that are not
+    // planar focus on a sphere instead.
+    pub focus_distance: f32,
+    // Shears the view sideways by this much per unit forward. Stereo eyes
+    // look straight ahead and are sheared to see the same things at the
+    // convergence distance.
+    pub shift: f32,
+}
+
+impl Camera {
+    pub fn new(position: (f32, f32, f32), vfov: f32, aspect: f32) -> Camera {
+        Camera {
+            position,
+            yaw: 0.,
+            pitch: 0.,
+            vfov,
+            aspect,
+            near: 0.,
+            far: 100.,
+            projection: Projection::Perspective,
+            aperture: 0.,
+            focus_distance: 5.,
+            shift: 0.,
+        }
+    }
+
+    pub fn look_at(&mut self, target: (f32, f32, f32)) {
+        let d = (
+            target.0 - self.position.0,
+            target.1 - self.position.1,
+            target.2 - self.position.2,
+        );
+        self.yaw = d.0.atan2(d.2);
+        self.pitch = d.1.atan2((d.0 * d.0 + d.2 * d.2).sqrt());
+    }
+
+    // Cameras for the left and right eye, moved sideways and sheared so
+    // their views line up at the convergence distance. Turning them in
+    // instead would tilt the views' planes apart, giving vertical parallax
+    // at the sides.
+    pub fn eyes(&self, stereo: &Stereo) -> (Camera, Camera) {
+        let (right, _, _) = self.basis();
+
+        let eye = |side: f32| {
+            let offset = side * stereo.eye_separation / 2.;
+            let mut eye = self.clone();
+            eye.position.0 += right.0 * offset;
+            eye.position.1 += right.1 * offset;
+            eye.position.2 += right.2 * offset;
+            eye.shift = self.shift - offset / stereo.convergence;
+            eye
+        };
+        (eye(-1.), eye(1.))
+    }
+
+    // Unit vectors pointing right, up and forward from the camera
+    pub fn basis(&self) -> Basis {
+        let (ysin, ycos) = self.yaw.sin_cos();
+        let (psin, pcos) = self.pitch.sin_cos();
+
+        let right = (ycos, 0., -ysin);
+        let up = (-psin * ysin, pcos, -psin * ycos);
+        let forward = (pcos * ysin, psin, pcos * ycos);
+        (right, up, forward)
+    }
+
+    // Turns a vector relative to the camera, (right, up, forward), into world
+    // coordinates
+    pub fn to_world(&self, v: (f32, f32, f32)) -> (f32, f32, f32) {
+        let (right, up, forward) = self.basis();
+        (
+            right.0 * v.0 + up.0 * v.1 + forward.0 * v.2,
+            right.1 * v.0 + up.1 * v.1 + forward.1 * v.2,
+            right.2 * v.0 + up.2 * v.1 + forward.2 * v.2,
+        )
+    }
+
+    // Ray origins and directions through screen coordinates, both of which
+    // go from -1 to 1, left to right and bottom to top. `lens` is where on
+    // the lens each ray passes, uniform in [0, 1) for both.
+    pub fn rays(&self, us: f32x16, vs: f32x16, lens: (f32x16, f32x16)) -> (Vec3dx16, Vec3dx16) {
+        let (mut offsets, mut local) = self.local_rays(us, vs);
+        local.xs += local.zs * self.shift;
+
+        if self.aperture > 0. {
+            let focus_dist = match self.projection {
+                Projection::Perspective | Projection::Orthographic { .. } => {
+                    f32x16::splat(self.focus_distance) / local.zs
+                }
+                _ => f32x16::splat(self.focus_distance) / norm(&local),
+            };
+            let focus = offsets + local * Vec3dx16::splat(focus_dist);
+
+            let r = lens.0.sqrt() * self.aperture;
+            let phi = lens.1 * (2. * PI);
+            offsets.xs += r * phi.cos();
+            offsets.ys += r * phi.sin();
+            local = focus - offsets;
+        }
+
+        let (right, up, forward) = self.basis();
+        let to_world = |v: &Vec3dx16| {
+            Vec3dx16::from_tuple(right) * Vec3dx16::splat(v.xs)
+                + Vec3dx16::from_tuple(up) * Vec3dx16::splat(v.ys)
+                + Vec3dx16::from_tuple(forward) * Vec3dx16::splat(v.zs)
+        };
+        let dirs = to_world(&local);
+
+        let origins = Vec3dx16::from_tuple(self.position)
+            + to_world(&offsets)
+            + dirs * Vec3dx16::splat(f32x16::splat(self.near) / norm(&dirs));
+        (origins, dirs)
+    }
+
+    // Which screen coordinates show the world, all but the letterbox around
+    // a cubemap
+    pub fn covers(&self, us: f32x16, vs: f32x16) -> m32x16 {
+        match self.projection {
+            Projection::Cubemap => {
+                let (xs, ys) = self.cubemap_grid(us, vs);
+                xs.abs().le(f32x16::splat(1.)) & ys.abs().le(f32x16::splat(1.))
+            }
+            _ => m32x16::splat(true),
+        }
+    }
+
+    // Screen coordinates scaled so the cubemap's grid goes from -1 to 1 with
+    // square faces, centered in the image
+    fn cubemap_grid(&self, us: f32x16, vs: f32x16) -> (f32x16, f32x16) {
+        let wide = self.aspect / 1.5;
+        (us * wide.max(1.), vs * (1. / wide).max(1.))
+    }
+
+    // Origin offsets and directions relative to the camera, as
+    // (right, up, forward). Directions are not necessarily normalized.
+    fn local_rays(&self, us: f32x16, vs: f32x16) -> (Vec3dx16, Vec3dx16) {
+        let zero = f32x16::splat(0.);
+        let no_offset = Vec3dx16::from_tuple((0., 0., 0.));
+
+        match self.projection {
+            Projection::Perspective => {
+                let tan = (self.vfov / 2.).tan();
+                let dirs = Vec3dx16 {
+                    xs: us * (tan * self.aspect),
+                    ys: vs * tan,
+                    zs: f32x16::splat(1.),
+                };
+                (no_offset, dirs)
+            }
+            Projection::Orthographic { height } => {
+                let offsets = Vec3dx16 {
+                    xs: us * (height / 2. * self.aspect),
+                    ys: vs * (height / 2.),
+                    zs: zero,
+                };
+                (offsets, Vec3dx16::from_tuple((0., 0., 1.)))
+            }
+            Projection::Fisheye => {
+                let xs = us * self.aspect;
+                let r = (xs * xs + vs * vs).sqrt();
+                let theta = r * (self.vfov / 2.);
+                // Avoid 0 / 0 in the very center
+                let scale = theta.sin() / r.max(f32x16::splat(1e-6));
+                let dirs = Vec3dx16 {
+                    xs: xs * scale,
+                    ys: vs * scale,
+                    zs: theta.cos(),
+                };
+                (no_offset, dirs)
+            }
+            Projection::Equirectangular => {
+                let lon = us * PI;
+                let lat = vs * (PI / 2.);
+                let dirs = Vec3dx16 {
+                    xs: lat.cos() * lon.sin(),
+                    ys: lat.sin(),
+                    zs: lat.cos() * lon.cos(),
+                };
+                (no_offset, dirs)
+            }
+            Projection::Cubemap => {
+                let (xs, ys) = self.cubemap_grid(us, vs);
+                let cols = (xs + 1.) * 1.5;
+                let rows = (1. - ys).min(f32x16::splat(1.999));
+                let col = floor(cols).max(zero).min(f32x16::splat(2.));
+                let row = floor(rows).max(zero);
+
+                // Coordinates within the face, from -1 to 1
+                let a = (cols - col) * 2. - 1.;
+                let b = 1. - (rows - row) * 2.;
+                let one = f32x16::splat(1.);
+
+                let face = row * 3. + col;
+                let mut dirs = Vec3dx16 { xs: one, ys: b, zs: -a };
+                let faces = [
+                    (face.eq(f32x16::splat(1.)), (-one, b, a)),
+                    (face.eq(f32x16::splat(2.)), (a, one, -b)),
+                    (face.eq(f32x16::splat(3.)), (a, -one, b)),
+                    (face.eq(f32x16::splat(4.)), (a, b, one)),
+                    (face.eq(f32x16::splat(5.)), (-a, b, -one)),
+                ];
+
+                for &(mask, (x, y, z)) in &faces {
+                    dirs.xs = mask.select(x, dirs.xs);
+                    dirs.ys = mask.select(y, dirs.ys);
+                    dirs.zs = mask.select(z, dirs.zs);
+                }
+                (no_offset, dirs)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Normalized direction through a point on a cubemap face, (a, b) going
+    // from -1 to 1 within it
+    fn cube_dir(camera: &Camera, face: usize, a: f32, b: f32) -> (f32, f32, f32) {
+        let (col, row) = ((face % 3) as f32, (face / 3) as f32);
+        let us = (col + (a + 1.) / 2.) / 1.5 - 1.;
+        let vs = 1. - (row + (1. - b) / 2.);
+
+        let zeros = f32x16::splat(0.);
+        let (_, dirs) = camera.rays(f32x16::splat(us), f32x16::splat(vs), (zeros, zeros));
+        let (x, y, z) = dirs.extract(0);
+        let len = (x * x + y * y + z * z).sqrt();
+        (x / len, y / len, z / len)
+    }
+
+    #[test]
+    fn cubemap_edges_match() {
+        let mut camera = Camera::new((0., 0., 0.), 1., 1.5);
+        camera.projection = Projection::Cubemap;
+
+        // Just inside every edge of every face, the same set of points along
+        // each edge so that shared edges are sampled at the same places. The
+        // bottom row stops a little short of the bottom of the image.
+        let edge = 0.997;
+        let along = [-edge, -0.6, -0.3, 0., 0.3, 0.6, edge];
+        let mut points = Vec::new();
+        for face in 0..6 {
+            for &t in &along {
+                for &(a, b) in &[(edge, t), (-edge, t), (t, edge), (t, -edge)] {
+                    points.push((face, cube_dir(&camera, face, a, b)));
+                }
+            }
+        }
+
+        for &(face, d) in &points {
+            let matched = points.iter().any(|&(other, e)| {
+                other != face && (d.0 - e.0).abs() + (d.1 - e.1).abs() + (d.2 - e.2).abs() < 1e-2
+            });
+            assert!(matched, "Edge of face {} at {:?} doesn't meet another face", face, d);
+        }
+    }
+
+    #[test]
+    fn cubemap_letterbox() {
+        let mut camera = Camera::new((0., 0., 0.), 1., 3.);
+        camera.projection = Projection::Cubemap;
+
+        // The grid is as wide as 1.5 times the height, in the middle
+        let us = f32x16::splat(0.).replace(1, 0.49).replace(2, 0.51).replace(3, -0.51);
+        let covers = camera.covers(us, f32x16::splat(0.9));
+        assert!(covers.extract(0) && covers.extract(1));
+        assert!(!covers.extract(2) && !covers.extract(3));
+
+        // Faces stay square, so the middle of +z looks straight ahead
+        let zeros = f32x16::splat(0.);
+        let (_, dirs) = camera.rays(f32x16::splat(0.), f32x16::splat(-0.5), (zeros, zeros));
+        assert_eq!(dirs.extract(0), (0., 0., 1.));
+    }
+
+    // Rays through the same pixels with different points on the lens
+    fn lens_rays(camera: &Camera) -> Vec<(Vec3dx16, Vec3dx16)> {
+        let us = f32x16::splat(-0.9).replace(1, 0.3).replace(2, 0.7).replace(3, -0.2);
+        let vs = f32x16::splat(0.5).replace(1, -0.8).replace(2, 0.1).replace(3, 0.95);
+        let lens = [(0., 0.), (0.25, 0.5), (0.9, 0.1), (0.5, 0.99)];
+        lens.iter()
+            .map(|&(a, b)| camera.rays(us, vs, (f32x16::splat(a), f32x16::splat(b))))
+            .collect()
+    }
+
+    #[test]
+    fn aperture() {
+        let mut camera = Camera::new((1., 2., 3.), 1., 1.5);
+        camera.yaw = 0.4;
+        camera.pitch = -0.2;
+        let (_, _, forward) = camera.basis();
+
+        for &projection in &[Projection::Perspective, Projection::Fisheye, Projection::Cubemap] {
+            camera.projection = projection;
+            camera.aperture = 0.;
+            let (origin, pinhole) = lens_rays(&camera)[0];
+
+            // Without an aperture, where on the lens rays pass makes no
+            // difference
+            for (origins, dirs) in lens_rays(&camera) {
+                for i in 0..16 {
+                    assert_eq!(origins.extract(i), origin.extract(i));
+                    assert_eq!(dirs.extract(i), pinhole.extract(i));
+                }
+            }
+
+            // With one, the rays of a pixel all pass through the point of the
+            // pinhole ray that is in focus
+            camera.aperture = 0.3;
+            let along = match projection {
+                Projection::Perspective => pinhole.dot(&Vec3dx16::from_tuple(forward)),
+                _ => norm(&pinhole),
+            };
+            let focus = origin + pinhole * Vec3dx16::splat(f32x16::splat(camera.focus_distance) / along);
+
+            for (k, (origins, dirs)) in lens_rays(&camera).into_iter().enumerate() {
+                // Only the center of the lens is where the pinhole was
+                assert_eq!(k == 0, origins.extract(0) == origin.extract(0));
+
+                let ends = origins + dirs;
+                for i in 0..16 {
+                    let (a, b) = (ends.extract(i), focus.extract(i));
+                    let error = (a.0 - b.0).abs() + (a.1 - b.1).abs() + (a.2 - b.2).abs();
+                    assert!(error < 1e-4, "{:?} misses the focus {:?}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn eyes_converge() {
+        let mut camera = Camera::new((1., 2., 3.), 1., 1.5);
+        camera.yaw = -0.7;
+        camera.pitch = 0.3;
+        let stereo = Stereo { mode: StereoMode::Anaglyph, eye_separation: 0.4, convergence: 6. };
+        let (left, right) = camera.eyes(&stereo);
+
+        // Both look the same way, so their views don't tilt apart
+        assert_eq!((left.yaw, left.pitch), (camera.yaw, camera.pitch));
+        assert_eq!((right.yaw, right.pitch), (camera.yaw, camera.pitch));
+
+        // Every pixel of the two views shows the same point at the
+        // convergence distance
+        let (_, _, forward) = camera.basis();
+        let forward = Vec3dx16::from_tuple(forward);
+        let us = f32x16::splat(0.).replace(1, 0.9).replace(2, -0.6);
+        let vs = f32x16::splat(0.).replace(1, -0.9).replace(2, 0.4);
+        let zeros = f32x16::splat(0.);
+
+        let at_convergence = |eye: &Camera| {
+            let (origins, dirs) = eye.rays(us, vs, (zeros, zeros));
+            let ahead = (origins - Vec3dx16::from_tuple(camera.position)).dot(&forward);
+            let along = (f32x16::splat(stereo.convergence) - ahead) / dirs.dot(&forward);
+            origins + dirs * Vec3dx16::splat(along)
+        };
+        let (l, r) = (at_convergence(&left), at_convergence(&right));
+        for i in 0..16 {
+            let (a, b) = (l.extract(i), r.extract(i));
+            let error = (a.0 - b.0).abs() + (a.1 - b.1).abs() + (a.2 - b.2).abs();
+            assert!(error < 1e-4, "{:?} and {:?} don't line up", a, b);
+        }
+    }
+}
@@ -0,0 +1,668 @@
+use crate::simd::{f32x16, m32x16};
+
+use std::borrow::Borrow;
+use std::f32::consts::PI;
+use std::marker::PhantomData;
+
+use crate::math::{acos, atan2, Mat3x4, Quat};
+use crate::vec::Vec3dx16;
+use crate::world::{norm, World};
+
+// What an orbit trap measures along the orbit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trap {
+    // Smallest distance to a point
+    Point((f32, f32, f32)),
+    // Smallest distance to the plane dot(z, normal) = offset
+    Plane((f32, f32, f32), f32),
+    // Smallest distance to the line through the origin along a direction
+    Axis((f32, f32, f32)),
+    Iterations,
+    // Iteration count, made continuous using how far past the bailout the
+    // orbit escaped
+    SmoothIterations,
+}
+
+// Per lane trap values, filled in by `Fractal::orbit`
+#[derive(Debug, Clone, Copy)]
+pub struct OrbitTrap {
+    pub kind: Trap,
+    pub distance: f32x16,
+    pub iterations: f32x16,
+    pub smooth: f32x16,
+}
+
+impl OrbitTrap {
+    pub fn new(kind: Trap) -> OrbitTrap {
+        OrbitTrap {
+            kind,
+            distance: f32x16::splat(f32::INFINITY),
+            iterations: f32x16::splat(0.),
+            smooth: f32x16::splat(0.),
+        }
+    }
+
+    fn measure(&self, z: &Vec3dx16) -> f32x16 {
+        match self.kind {
+            Trap::Point(p) => norm(&(z - Vec3dx16::from_tuple(p))),
+            Trap::Plane(normal, offset) => (z.dot(&Vec3dx16::from_tuple(normal)) - offset).abs(),
+            Trap::Axis(dir) => {
+                let len = (dir.0 * dir.0 + dir.1 * dir.1 + dir.2 * dir.2).sqrt();
+                let dir = Vec3dx16::from_tuple((dir.0 / len, dir.1 / len, dir.2 / len));
+                norm(&(z - dir * Vec3dx16::splat(z.dot(&dir))))
+            }
+            Trap::Iterations | Trap::SmoothIterations => self.distance,
+        }
+    }
+
+    // Called with the point the orbit starts at, before any iteration, so
+    // lanes that start past the bailout still get a distance
+    pub fn start(&mut self, z: &Vec3dx16) {
+        self.distance = self.distance.min(self.measure(z));
+    }
+
+    // Called once per iteration with the new orbit point. Only lanes in
+    // `active` are updated.
+    pub fn record(&mut self, z: &Vec3dx16, active: m32x16) {
+        let distance = self.measure(z);
+
+        self.distance = active.select(self.distance.min(distance), self.distance);
+        self.iterations = active.select(self.iterations + 1., self.iterations);
+        self.smooth = active.select(self.iterations, self.smooth);
+    }
+
+    // Called with the orbit radius for lanes that just went past the bailout
+    pub fn escape(&mut self, r: f32x16, escaping: m32x16, bailout: f32, power: f32) {
+        let past = (r.ln() / bailout.ln()).ln() / power.ln();
+        self.smooth = escaping.select(self.iterations - past, self.smooth);
+    }
+
+    pub fn value(&self) -> f32x16 {
+        match self.kind {
+            Trap::Point(_) | Trap::Plane(..) | Trap::Axis(_) => self.distance,
+            Trap::Iterations => self.iterations,
+            Trap::SmoothIterations => self.smooth,
+        }
+    }
+}
+
+pub enum Palette {
+    // Piecewise linear between (position, color) stops, sorted by position
+    Gradient(Vec<(f32, (f32, f32, f32))>),
+    // a + b * cos(2 pi (c t + d)), per channel
+    Cosine {
+        a: (f32, f32, f32),
+        b: (f32, f32, f32),
+        c: (f32, f32, f32),
+        d: (f32, f32, f32),
+    },
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::Cosine {
+            a: (0.5, 0.5, 0.5),
+            b: (0.5, 0.5, 0.5),
+            c: (1., 1., 1.),
+            d: (0., 0.1, 0.2),
+        }
+    }
+}
+
+impl Palette {
+    pub fn sample(&self, t: f32x16) -> Vec3dx16 {
+        match self {
+            Palette::Gradient(stops) => {
+                let mut res = match stops.first() {
+                    Some(&(_, color)) => Vec3dx16::from_tuple(color),
+                    None => return Vec3dx16::from_tuple((0., 0., 0.)),
+                };
+                for pair in stops.windows(2) {
+                    let (t0, c0) = pair[0];
+                    let (t1, c1) = pair[1];
+                    let f = ((t - t0) / (t1 - t0).max(1e-6))
+                        .max(f32x16::splat(0.))
+                        .min(f32x16::splat(1.));
+                    let color = Vec3dx16::from_tuple(c0)
+                        + (Vec3dx16::from_tuple(c1) - Vec3dx16::from_tuple(c0)) * Vec3dx16::splat(f);
+
+                    let past = t.ge(f32x16::splat(t0));
+                    res.xs = past.select(color.xs, res.xs);
+                    res.ys = past.select(color.ys, res.ys);
+                    res.zs = past.select(color.zs, res.zs);
+                }
+                res
+            }
+            Palette::Cosine { a, b, c, d } => {
+                let channel = |a: f32, b: f32, c: f32, d: f32| a + b * (2. * PI * (t * c + d)).cos();
+                Vec3dx16 {
+                    xs: channel(a.0, b.0, c.0, d.0),
+                    ys: channel(a.1, b.1, c.1, d.1),
+                    zs: channel(a.2, b.2, c.2, d.2),
+                }
+            }
+        }
+    }
+}
+
+// A fractal computes its distance estimate and its orbit trap in the same
+// pass, so coloring does not need to iterate a second time
+pub trait Fractal: Send + Sync {
+    fn orbit(&self, x: &Vec3dx16, trap: &mut OrbitTrap) -> f32x16;
+}
+
+// Trap used by fractals that are worlds on their own, without `Trapped`
+const DEFAULT_TRAP: Trap = Trap::Point((0., 0., 0.));
+
+// Every fractal is a world, colored by how close its orbit comes to the
+// origin with the default palette
+impl <F: Fractal> World for F {
+    fn distance_estimator(&self, x: &Vec3dx16, _time: f32x16) -> f32x16 {
+        self.orbit(x, &mut OrbitTrap::new(DEFAULT_TRAP))
+    }
+
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.distance_color(x, time).1
+    }
+
+    fn distance_color(&self, x: &Vec3dx16, _time: f32x16) -> (f32x16, Vec3dx16) {
+        let mut trap = OrbitTrap::new(DEFAULT_TRAP);
+        let distance = self.orbit(x, &mut trap);
+        (distance, Palette::default().sample(trap.value()))
+    }
+}
+
+// Turns a fractal into a world colored by one of its orbit traps
+pub struct Trapped<F: Fractal> {
+    pub fractal: F,
+    pub trap: Trap,
+    pub palette: Palette,
+}
+
+impl <F: Fractal> Trapped<F> {
+    pub fn new(fractal: F, trap: Trap, palette: Palette) -> Trapped<F> {
+        Trapped { fractal, trap, palette }
+    }
+}
+
+impl <F: Fractal> World for Trapped<F> {
+    fn distance_estimator(&self, x: &Vec3dx16, _time: f32x16) -> f32x16 {
+        self.fractal.orbit(x, &mut OrbitTrap::new(self.trap))
+    }
+
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.distance_color(x, time).1
+    }
+
+    fn distance_color(&self, x: &Vec3dx16, _time: f32x16) -> (f32x16, Vec3dx16) {
+        let mut trap = OrbitTrap::new(self.trap);
+        let distance = self.fractal.orbit(x, &mut trap);
+        (distance, self.palette.sample(trap.value()))
+    }
+}
+
+pub struct Mandelbulb {
+    pub power: f32,
+    pub iterations: usize,
+    pub bailout: f32,
+}
+
+impl Default for Mandelbulb {
+    fn default() -> Mandelbulb {
+        Mandelbulb {
+            power: 8.,
+            iterations: 8,
+            bailout: 2.,
+        }
+    }
+}
+
+impl Fractal for Mandelbulb {
+    fn orbit(&self, x: &Vec3dx16, trap: &mut OrbitTrap) -> f32x16 {
+        let mut z = *x;
+        let mut dr = f32x16::splat(1.);
+        let mut r = norm(&z);
+        let mut escaped = r.gt(f32x16::splat(self.bailout));
+        trap.start(&z);
+
+        let power = f32x16::splat(self.power);
+
+        for _ in 0..self.iterations {
+            if escaped.all() {
+                break;
+            }
+
+            let theta = acos(z.zs / r.max(f32x16::splat(1e-20))) * self.power;
+            let phi = atan2(z.ys, z.xs) * self.power;
+            let zr = r.powf(power);
+
+            let dr_ = r.powf(power - 1.) * self.power * dr + 1.;
+            let z_ = Vec3dx16 {
+                xs: zr * theta.sin() * phi.cos(),
+                ys: zr * theta.sin() * phi.sin(),
+                zs: zr * theta.cos(),
+            } + x;
+
+            dr = escaped.select(dr, dr_);
+            z.xs = escaped.select(z.xs, z_.xs);
+            z.ys = escaped.select(z.ys, z_.ys);
+            z.zs = escaped.select(z.zs, z_.zs);
+
+            r = norm(&z);
+            trap.record(&z, !escaped);
+            let escaping = r.gt(f32x16::splat(self.bailout)) & !escaped;
+            trap.escape(r, escaping, self.bailout, self.power);
+            escaped |= escaping;
+        }
+
+        0.5 * r.ln() * r / dr
+    }
+}
+
+pub struct Mandelbox {
+    pub scale: f32,
+    pub min_radius: f32,
+    pub fixed_radius: f32,
+    pub folding_limit: f32,
+    pub iterations: usize,
+}
+
+impl Default for Mandelbox {
+    fn default() -> Mandelbox {
+        Mandelbox {
+            scale: 2.,
+            min_radius: 0.5,
+            fixed_radius: 1.,
+            folding_limit: 1.,
+            iterations: 12,
+        }
+    }
+}
+
+impl Fractal for Mandelbox {
+    fn orbit(&self, x: &Vec3dx16, trap: &mut OrbitTrap) -> f32x16 {
+        let mut z = *x;
+        let mut dr = f32x16::splat(1.);
+
+        let limit = f32x16::splat(self.folding_limit);
+        let min_r2 = f32x16::splat(self.min_radius * self.min_radius);
+        let fixed_r2 = f32x16::splat(self.fixed_radius * self.fixed_radius);
+
+        for _ in 0..self.iterations {
+            // Box fold
+            let fold = |v: f32x16| v.max(-limit).min(limit) * 2. - v;
+            z.xs = fold(z.xs);
+            z.ys = fold(z.ys);
+            z.zs = fold(z.zs);
+
+            // Sphere fold
+            let r2 = z.dot(&z);
+            let factor = r2.lt(min_r2).select(
+                fixed_r2 / min_r2,
+                r2.lt(fixed_r2).select(fixed_r2 / r2, f32x16::splat(1.)),
+            );
+            z *= Vec3dx16::splat(factor);
+            dr *= factor;
+
+            z = z * Vec3dx16::splat(f32x16::splat(self.scale)) + x;
+            dr = dr * self.scale.abs() + 1.;
+
+            trap.record(&z, m32x16::splat(true));
+        }
+
+        norm(&z) / dr.abs()
+    }
+}
+
+// A 3D slice of the quaternion Julia set. Points map to quaternions with x
+// as the real part, and the last imaginary part fixed to `slice`.
+pub struct QuaternionJulia {
+    pub c: Quat,
+    pub slice: f32,
+    pub iterations: usize,
+    pub bailout: f32,
+}
+
+impl Default for QuaternionJulia {
+    fn default() -> QuaternionJulia {
+        QuaternionJulia {
+            c: Quat { w: -0.2, x: 0.8, y: 0., z: 0. },
+            slice: 0.,
+            iterations: 11,
+            bailout: 4.,
+        }
+    }
+}
+
+impl Fractal for QuaternionJulia {
+    fn orbit(&self, x: &Vec3dx16, trap: &mut OrbitTrap) -> f32x16 {
+        let (mut a, mut b, mut c, mut d) = (x.xs, x.ys, x.zs, f32x16::splat(self.slice));
+        let mut mz2 = a * a + b * b + c * c + d * d;
+        // Squared length of the running derivative
+        let mut md2 = f32x16::splat(1.);
+        let bailout2 = f32x16::splat(self.bailout * self.bailout);
+        let mut escaped = mz2.gt(bailout2);
+        trap.start(x);
+
+        for _ in 0..self.iterations {
+            if escaped.all() {
+                break;
+            }
+
+            let md2_ = md2 * mz2 * 4.;
+            let a_ = a * a - b * b - c * c - d * d + self.c.w;
+            let b_ = a * b * 2. + self.c.x;
+            let c_ = a * c * 2. + self.c.y;
+            let d_ = a * d * 2. + self.c.z;
+
+            md2 = escaped.select(md2, md2_);
+            a = escaped.select(a, a_);
+            b = escaped.select(b, b_);
+            c = escaped.select(c, c_);
+            d = escaped.select(d, d_);
+
+            mz2 = a * a + b * b + c * c + d * d;
+            // The trap only sees the first three components of the orbit
+            trap.record(&Vec3dx16 { xs: a, ys: b, zs: c }, !escaped);
+            let escaping = mz2.gt(bailout2) & !escaped;
+            trap.escape(mz2.sqrt(), escaping, self.bailout, 2.);
+            escaped |= escaping;
+        }
+
+        0.25 * (mz2 / md2).sqrt() * mz2.ln()
+    }
+}
+
+fn swap_where(mask: m32x16, a: &mut f32x16, b: &mut f32x16) {
+    let (a_, b_) = (*a, *b);
+    *a = mask.select(b_, a_);
+    *b = mask.select(a_, b_);
+}
+
+pub struct MengerSponge {
+    pub iterations: usize,
+    pub scale: f32,
+    pub offset: (f32, f32, f32),
+}
+
+impl Default for MengerSponge {
+    fn default() -> MengerSponge {
+        MengerSponge {
+            iterations: 5,
+            scale: 3.,
+            offset: (1., 1., 1.),
+        }
+    }
+}
+
+impl Fractal for MengerSponge {
+    fn orbit(&self, x: &Vec3dx16, trap: &mut OrbitTrap) -> f32x16 {
+        let mut p = *x;
+        let s = self.scale - 1.;
+
+        for _ in 0..self.iterations {
+            p.xs = p.xs.abs();
+            p.ys = p.ys.abs();
+            p.zs = p.zs.abs();
+
+            // Sort so that x >= y >= z
+            swap_where(p.xs.lt(p.ys), &mut p.xs, &mut p.ys);
+            swap_where(p.xs.lt(p.zs), &mut p.xs, &mut p.zs);
+            swap_where(p.ys.lt(p.zs), &mut p.ys, &mut p.zs);
+
+            p.xs = p.xs * self.scale - self.offset.0 * s;
+            p.ys = p.ys * self.scale - self.offset.1 * s;
+            p.zs *= self.scale;
+            let shift = p.zs.gt(f32x16::splat(0.5 * self.offset.2 * s));
+            p.zs = shift.select(p.zs - self.offset.2 * s, p.zs);
+
+            trap.record(&p, m32x16::splat(true));
+        }
+
+        let cube = p.xs.abs().max(p.ys.abs()).max(p.zs.abs()) - 1.;
+        cube * self.scale.powi(-(self.iterations as i32))
+    }
+}
+
+pub struct SierpinskiTetrahedron {
+    pub iterations: usize,
+    pub scale: f32,
+    pub offset: (f32, f32, f32),
+}
+
+impl Default for SierpinskiTetrahedron {
+    fn default() -> SierpinskiTetrahedron {
+        SierpinskiTetrahedron {
+            iterations: 10,
+            scale: 2.,
+            offset: (1., 1., 1.),
+        }
+    }
+}
+
+impl Fractal for SierpinskiTetrahedron {
+    fn orbit(&self, x: &Vec3dx16, trap: &mut OrbitTrap) -> f32x16 {
+        let mut p = *x;
+        let s = self.scale - 1.;
+        let zero = f32x16::splat(0.);
+
+        for _ in 0..self.iterations {
+            // Reflect in the planes x = -y, x = -z and y = -z
+            let fold = (p.xs + p.ys).lt(zero);
+            let (xs, ys) = (-p.ys, -p.xs);
+            p.xs = fold.select(xs, p.xs);
+            p.ys = fold.select(ys, p.ys);
+
+            let fold = (p.xs + p.zs).lt(zero);
+            let (xs, zs) = (-p.zs, -p.xs);
+            p.xs = fold.select(xs, p.xs);
+            p.zs = fold.select(zs, p.zs);
+
+            let fold = (p.ys + p.zs).lt(zero);
+            let (ys, zs) = (-p.zs, -p.ys);
+            p.ys = fold.select(ys, p.ys);
+            p.zs = fold.select(zs, p.zs);
+
+            p.xs = p.xs * self.scale - self.offset.0 * s;
+            p.ys = p.ys * self.scale - self.offset.1 * s;
+            p.zs = p.zs * self.scale - self.offset.2 * s;
+
+            trap.record(&p, m32x16::splat(true));
+        }
+
+        norm(&p) * self.scale.powi(-(self.iterations as i32))
+    }
+}
+
+// Kaleidoscopic IFS: each iteration folds through all planes, rotates, then
+// scales about `offset`. The inner world is sampled in the folded space.
+pub struct Kifs<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    // Normals of fold planes through the origin, points end up on the side
+    // the normal points to
+    pub folds: Vec<(f32, f32, f32)>,
+    pub rotation: Quat,
+    pub scale: f32,
+    pub offset: (f32, f32, f32),
+    pub iterations: usize,
+    marker: PhantomData<TBor>
+}
+
+#[allow(dead_code)]
+pub type KifsRef<'a, T> = Kifs<&'a T, T>;
+#[allow(dead_code)]
+pub type KifsT<T> = Kifs<T, T>;
+
+impl <T, TBor> Kifs<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub fn new(inner: T, scale: f32, offset: (f32, f32, f32), iterations: usize) -> Kifs<T, TBor> {
+        Kifs {
+            inner,
+            folds: Vec::new(),
+            rotation: Quat::identity(),
+            scale,
+            offset,
+            iterations,
+            marker: PhantomData
+        }
+    }
+
+    pub fn fold(mut self, normal: (f32, f32, f32)) -> Kifs<T, TBor> {
+        let len = (normal.0 * normal.0 + normal.1 * normal.1 + normal.2 * normal.2).sqrt();
+        self.folds.push((normal.0 / len, normal.1 / len, normal.2 / len));
+        self
+    }
+
+    pub fn rotate(mut self, rotation: Quat) -> Kifs<T, TBor> {
+        self.rotation = rotation;
+        self
+    }
+
+    fn transform(&self, x: &Vec3dx16) -> Vec3dx16 {
+        let rotation = Mat3x4::from_parts(self.rotation, (0., 0., 0.), 1.);
+        let offset = Vec3dx16::from_tuple(self.offset) * Vec3dx16::splat(f32x16::splat(self.scale - 1.));
+        let scale = Vec3dx16::splat(f32x16::splat(self.scale));
+
+        let mut p = *x;
+        for _ in 0..self.iterations {
+            for &normal in &self.folds {
+                let normal = Vec3dx16::from_tuple(normal);
+                let side = p.dot(&normal).min(f32x16::splat(0.));
+                p -= normal * Vec3dx16::splat(side * 2.);
+            }
+            p = rotation.apply(&p) * scale - offset;
+        }
+        p
+    }
+}
+
+impl <T, TBor> World for Kifs<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x), time)
+            * self.scale.powi(-(self.iterations as i32))
+    }
+
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x), time)
+    }
+
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (distance, color) = self.inner.borrow().distance_color(&self.transform(x), time);
+        (distance * self.scale.powi(-(self.iterations as i32)), color)
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::tests::{assert_conservative, assert_conservative_near, random_points, region};
+    use crate::world::UnitSphere;
+
+    #[test]
+    fn mandelbulb_is_conservative() {
+        assert_conservative(&Mandelbulb::default(), region(1.5));
+        assert_conservative(&Mandelbulb { power: 4., ..Mandelbulb::default() }, region(1.5));
+    }
+
+    #[test]
+    fn mandelbox_is_conservative() {
+        // The estimate never goes below zero
+        assert_conservative_near(&Mandelbox { scale: -1.5, ..Mandelbox::default() }, region(2.5), 0.02);
+    }
+
+    #[test]
+    fn julia_is_conservative() {
+        assert_conservative(&QuaternionJulia::default(), region(1.5));
+    }
+
+    #[test]
+    fn menger_sponge_is_conservative() {
+        assert_conservative(&MengerSponge::default(), region(1.5));
+    }
+
+    #[test]
+    fn ifs_are_conservative() {
+        assert_conservative_near(&SierpinskiTetrahedron::default(), region(1.5), 0.02);
+
+        let kifs = Kifs::new(UnitSphere, 2., (1., 1., 1.), 6)
+            .fold((1., 1., 0.))
+            .fold((1., 0., 1.))
+            .fold((0., 1., 1.))
+            .rotate(Quat::from_axis_angle((1., 1., 0.), 0.3));
+        assert_conservative_near(&kifs, region(2.), 0.02);
+    }
+
+    // Every kind of trap gives values the palettes expect: distances that
+    // are finite and not negative, and iteration counts between zero and the
+    // number of iterations
+    #[test]
+    fn traps_stay_in_range() {
+        let traps = [
+            Trap::Point((0.2, 0.1, 0.)),
+            Trap::Plane((0., 1., 0.), 0.5),
+            Trap::Axis((1., 1., 1.)),
+            Trap::Iterations,
+            Trap::SmoothIterations,
+        ];
+        let fractals: [(&dyn Fractal, usize); 5] = [
+            (&Mandelbulb::default(), 8),
+            (&Mandelbox::default(), 12),
+            (&QuaternionJulia::default(), 11),
+            (&MengerSponge::default(), 5),
+            (&SierpinskiTetrahedron::default(), 10),
+        ];
+
+        for &(fractal, iterations) in &fractals {
+            for &kind in &traps {
+                for seed in 0..32 {
+                    let x = random_points(&region(2.), seed);
+                    let mut trap = OrbitTrap::new(kind);
+                    fractal.orbit(&x, &mut trap);
+
+                    for lane in 0..16 {
+                        let value = trap.value().extract(lane);
+                        match kind {
+                            Trap::Iterations | Trap::SmoothIterations => {
+                                // A smooth count can go below the step it
+                                // escaped at by up to one
+                                assert!(value >= -1. && value <= iterations as f32, "{:?} {}", kind, value);
+                            }
+                            _ => assert!(value.is_finite() && value >= 0., "{:?} {}", kind, value),
+                        }
+                    }
+                }
+            }
+        }
+
+        // Colors from the palettes stay displayable
+        let palettes = [
+            Palette::default(),
+            Palette::Gradient(vec![(0., (1., 0.5, 0.)), (2., (0., 0.2, 1.))]),
+        ];
+        for palette in &palettes {
+            for seed in 0..32 {
+                let t = random_points(&region(10.), seed).xs;
+                let color = palette.sample(t);
+                for lane in 0..16 {
+                    let (r, g, b) = color.extract(lane);
+                    assert!([r, g, b].iter().all(|c| (0. ..=1.).contains(c)), "{:?}", (r, g, b));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// An RGBA image that can be split into disjoint tiles, so that several
+// threads can write into it at once without any unsafe code
+pub struct Framebuffer<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+}
+
+pub struct Tile<'a> {
+    // Position of the top left corner in the framebuffer
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    rows: Vec<&'a mut [u8]>,
+}
+
+impl<'a> Framebuffer<'a> {
+    pub fn new(data: &'a mut [u8], width: usize, height: usize) -> Framebuffer<'a> {
+        assert_eq!(data.len(), 4 * width * height, "Framebuffer has the wrong size");
+
+        Framebuffer { data, width, height }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Splits the image into tiles of `size` by `size` pixels, smaller along
+    // the right and bottom edges
+    pub fn tiles(&mut self, size: usize) -> Vec<Tile<'_>> {
+        let (width, height) = (self.width, self.height);
+        let mut tiles = Vec::new();
+
+        if width == 0 || height == 0 {
+            return tiles;
+        }
+
+        for (ty, band) in self.data.chunks_mut(4 * width * size).enumerate() {
+            let mut row_tiles: Vec<Tile> = (0..width.div_ceil(size))
+                .map(|tx| Tile {
+                    x: tx * size,
+                    y: ty * size,
+                    width: size.min(width - tx * size),
+                    height: size.min(height - ty * size),
+                    rows: Vec::with_capacity(size),
+                })
+                .collect();
+
+            for row in band.chunks_mut(4 * width) {
+                for (tile, part) in row_tiles.iter_mut().zip(row.chunks_mut(4 * size)) {
+                    tile.rows.push(part);
+                }
+            }
+
+            tiles.extend(row_tiles);
+        }
+
+        tiles
+    }
+}
+
+impl<'a> Tile<'a> {
+    // Coordinates are relative to the tile, colors are between 0 and 255
+    pub fn set(&mut self, x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+        let pixel = &mut self.rows[y][4 * x..4 * x + 4];
+        pixel.copy_from_slice(&[r, g, b, 255]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every pixel belongs to exactly one tile, also when the size isn't a
+    // multiple of the tile size
+    #[test]
+    fn tiles_cover_image_once() {
+        for &(width, height) in &[(1, 1), (5, 3), (17, 33), (37, 20), (64, 48)] {
+            for &size in &[4, 16, 32] {
+                let mut data = vec![0; 4 * width * height];
+                let mut framebuffer = Framebuffer::new(&mut data, width, height);
+
+                for (i, mut tile) in framebuffer.tiles(size).into_iter().enumerate() {
+                    assert_eq!(tile.x % size, 0);
+                    assert_eq!(tile.y % size, 0);
+                    assert_eq!(tile.width, size.min(width - tile.x));
+                    assert_eq!(tile.height, size.min(height - tile.y));
+                    assert_eq!(tile.rows.len(), tile.height);
+
+                    for y in 0..tile.height {
+                        assert_eq!(tile.rows[y].len(), 4 * tile.width);
+                        for x in 0..tile.width {
+                            // Count the writes in the alpha channel
+                            let alpha = tile.rows[y][4 * x + 3];
+                            tile.set(x, y, (i as u8, 0, 0));
+                            tile.rows[y][4 * x + 3] = alpha + 1;
+                        }
+                    }
+                }
+
+                for y in 0..height {
+                    for x in 0..width {
+                        let pixel = &data[4 * (y * width + x)..][..4];
+                        let tiles_across = width.div_ceil(size);
+                        let tile = (y / size) * tiles_across + x / size;
+                        assert_eq!(pixel, &[tile as u8, 0, 0, 1], "{}x{} tiles of {}", width, height, size);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,16 @@
-#![feature(stdsimd)]
+#![feature(portable_simd)]
 
+mod bench;
+mod bvh;
+mod camera;
+mod fractal;
+mod framebuffer;
+mod march;
+mod math;
+mod noise;
 mod renderer;
+mod scenes;
+mod simd;
 mod vec;
 mod world;
 
@@ -9,39 +19,225 @@ use sdl2::keyboard::Keycode;
 use ytesrev::prelude::*;
 use ytesrev::window::{WSETTINGS_MAIN, WindowSettings};
 
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+use camera::StereoMode;
+use march::Strategy;
+
+// Parses a size like "1280x720"
+fn parse_size(arg: &str) -> Option<(usize, usize)> {
+    let mut parts = arg.split('x');
+    let width = parts.next()?.parse().ok()?;
+    let height = parts.next()?.parse().ok()?;
+
+    if parts.next().is_some() || width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+// Parses a point like "1,-2,3.5"
+fn parse_point(arg: &str) -> Option<(f32, f32, f32)> {
+    let mut parts = arg.split(',');
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    let z = parts.next()?.parse().ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((x, y, z))
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: raymarching [WIDTHxHEIGHT] [--bench] [--iterations N] [--epsilon E] \
+         [--pixel-cone] [--max-distance D] [--step-scale S] [--strategy sphere|relaxed|enhanced] \
+         [--position X,Y,Z] [--look-at X,Y,Z] [--scene NAME] [--spheres N] [--samples N] \
+         [--time T] [--shutter S] [--aperture R] [--focus D] \
+         [--stereo mono|side-by-side|anaglyph] [--eye-separation S] [--convergence D] \
+         [--output FILE.ppm]"
+    );
+    eprintln!("Scenes: {}", scenes::Scene::names().join(", "));
+    std::process::exit(1);
+}
+
+// The value following an option
+fn value<T: FromStr>(args: &mut impl Iterator<Item = String>) -> T {
+    args.next().and_then(|arg| arg.parse().ok()).unwrap_or_else(|| usage())
+}
+
+// The point following an option
+fn point(args: &mut impl Iterator<Item = String>) -> (f32, f32, f32) {
+    parse_point(&value::<String>(args)).unwrap_or_else(|| usage())
+}
+
+// Renders a single frame without opening a window and saves it as a binary
+// PPM. The RGBA data is written without its alpha.
+fn render_to_file(renderer: &renderer::Renderer, width: usize, height: usize, path: &str) -> io::Result<()> {
+    let mut data = vec![0; 4 * width * height];
+    renderer.render_into(&mut framebuffer::Framebuffer::new(&mut data, width, height));
+
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{} {}\n255\n", width, height)?;
+    for pixel in data.chunks(4) {
+        file.write_all(&pixel[..3])?;
+    }
+    file.flush()
+}
+
 fn main() {
+    let mut size = renderer::DEFAULT_SIZE;
+    let mut benchmark = false;
+    let mut pixel_cone = false;
+    let mut iterations = None;
+    let mut epsilon = None;
+    let mut max_distance = None;
+    let mut step_scale = None;
+    let mut strategy = None;
+    let mut position = None;
+    let mut target = None;
+    let mut scene = None;
+    let mut spheres = None;
+    let mut samples = None;
+    let mut time = None;
+    let mut shutter = None;
+    let mut aperture = None;
+    let mut focus = None;
+    let mut stereo = None;
+    let mut eye_separation = None;
+    let mut convergence = None;
+    let mut output: Option<String> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bench" => benchmark = true,
+            "--pixel-cone" => pixel_cone = true,
+            "--iterations" => iterations = Some(value(&mut args)),
+            "--epsilon" => epsilon = Some(value(&mut args)),
+            "--max-distance" => max_distance = Some(value(&mut args)),
+            "--step-scale" => step_scale = Some(value(&mut args)),
+            "--position" => position = Some(point(&mut args)),
+            "--look-at" => target = Some(point(&mut args)),
+            "--scene" => {
+                let name: String = value(&mut args);
+                scene = Some(scenes::Scene::from_name(&name).unwrap_or_else(|| usage()));
+            }
+            "--spheres" => spheres = Some(value(&mut args)),
+            "--samples" => samples = Some(value(&mut args)),
+            "--time" => time = Some(value(&mut args)),
+            "--shutter" => shutter = Some(value(&mut args)),
+            "--aperture" => aperture = Some(value(&mut args)),
+            "--focus" => focus = Some(value(&mut args)),
+            "--stereo" => {
+                stereo = match value::<String>(&mut args).as_str() {
+                    "mono" => Some(StereoMode::Mono),
+                    "side-by-side" => Some(StereoMode::SideBySide),
+                    "anaglyph" => Some(StereoMode::Anaglyph),
+                    _ => usage(),
+                }
+            }
+            "--eye-separation" => eye_separation = Some(value(&mut args)),
+            "--convergence" => convergence = Some(value(&mut args)),
+            "--output" => output = Some(value(&mut args)),
+            "--strategy" => {
+                strategy = match value::<String>(&mut args).as_str() {
+                    "sphere" => Some(Strategy::Sphere),
+                    "relaxed" => Some(Strategy::over_relaxed()),
+                    "enhanced" => Some(Strategy::enhanced()),
+                    _ => usage(),
+                }
+            }
+            _ => size = parse_size(&arg).unwrap_or_else(|| usage()),
+        }
+    }
+
+    let (width, height) = size;
+    let mut renderer = renderer::Renderer::new(width, height);
+
+    if let Some(scene) = scene {
+        renderer.set_scene(scenes::SceneSettings::new(scene));
+    }
+    // Shorthand for the spheres scene with another number of spheres
+    if let Some(count) = spheres {
+        let spheres = scenes::SceneSettings::new(scenes::Scene::Spheres);
+        renderer.set_scene(scenes::SceneSettings { detail: count, ..spheres });
+    }
+    let camera = renderer.camera_mut();
+    camera.position = position.unwrap_or(camera.position);
+    if let Some(target) = target {
+        camera.look_at(target);
+    }
+    renderer.set_pixel_cone(pixel_cone);
+    if let Some(samples) = samples {
+        renderer.set_samples(samples);
+    }
+    if let Some(time) = time {
+        renderer.set_time(time);
+    }
+    if let Some(shutter) = shutter {
+        renderer.set_shutter(shutter);
+    }
+    if let Some(aperture) = aperture {
+        renderer.set_aperture(aperture);
+    }
+    if let Some(focus) = focus {
+        renderer.set_focus_distance(focus);
+    }
+    if let Some(far) = max_distance {
+        renderer.set_far(far);
+    }
+    let settings = renderer.stereo_mut();
+    settings.mode = stereo.unwrap_or(settings.mode);
+    settings.eye_separation = eye_separation.unwrap_or(settings.eye_separation);
+    settings.convergence = convergence.unwrap_or(settings.convergence);
+
+    let settings = renderer.march_settings_mut();
+    settings.iterations = iterations.unwrap_or(settings.iterations);
+    settings.epsilon = epsilon.unwrap_or(settings.epsilon);
+    // Like the key bindings, steps are never lengthened
+    settings.step_scale = step_scale.unwrap_or(settings.step_scale).clamp(0.1, 1.);
+    settings.strategy = strategy.unwrap_or(settings.strategy);
+
+    if let Some(path) = output {
+        if let Err(err) = render_to_file(&renderer, width, height, &path) {
+            eprintln!("Can't write {}: {}", path, err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if benchmark {
+        bench::run(renderer, width, height);
+        return;
+    }
+
     let mut wmng = WindowManager::init_window(
-        renderer::Renderer::new(),
+        renderer,
         WindowManagerSettings {
             windows: vec![
                 ("renderer".into(),
                 WindowSettings {
-                    window_size: (renderer::SIZE as u32, renderer::SIZE as u32),
+                    window_size: (width as u32, height as u32),
                     ..WSETTINGS_MAIN
                 }
             )],
-            event_step_rule: Box::new(|event| match event {
-                Event::KeyDown {
-                    keycode: Some(Keycode::Space),
-                    ..
-                } => true,
-                Event::MouseButtonDown { .. } => true,
-                _ => false,
+            event_step_rule: Box::new(|event| {
+                matches!(
+                    event,
+                    Event::KeyDown { keycode: Some(Keycode::Space), .. } | Event::MouseButtonDown { .. }
+                )
             }),
-            quit_rule: Box::new(|event| match event {
-                Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => true,
-                Event::Quit { .. } => true,
-                _ => false,
+            quit_rule: Box::new(|event| {
+                matches!(event, Event::KeyDown { keycode: Some(Keycode::Escape), .. } | Event::Quit { .. })
             }),
         },
     );
 
-    unsafe {
-        renderer::MOUSE = Some(wmng.context.mouse());
-    }
+    renderer::MOUSE.with(|mouse| *mouse.borrow_mut() = Some(wmng.context.mouse()));
 
 
     wmng.start();
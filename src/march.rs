@@ -0,0 +1,473 @@
+use crate::simd::{f32x16, m32x16};
+
+
+use crate::vec::Vec3dx16;
+use crate::world::{norm, World};
+
+// How far each step goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    // The distance estimate, the largest step known not to pass a surface
+    Sphere,
+    // The distance estimate times `relaxation`, between 1 and 2. When the
+    // unbounding spheres before and after a step don't overlap, the step may
+    // have passed a surface, so the ray goes back and continues as `Sphere`.
+    OverRelaxed { relaxation: f32 },
+    // Steps most of the way to where the spheres stop overlapping, assuming
+    // the surface is a plane whose slope is estimated from the last two
+    // distances, and at most `relaxation` times the distance. A step that
+    // goes too far is retried as a `Sphere` step. Rays that run out of
+    // iterations end where they came closest to a surface relative to how far
+    // they went.
+    Enhanced { relaxation: f32 },
+}
+
+impl Strategy {
+    pub fn over_relaxed() -> Strategy {
+        Strategy::OverRelaxed { relaxation: 1.2 }
+    }
+
+    pub fn enhanced() -> Strategy {
+        Strategy::Enhanced { relaxation: 1.9 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarchSettings {
+    // Steps per ray before giving up
+    pub iterations: usize,
+    // Distance estimates below this count as hits
+    pub epsilon: f32,
+    // How much epsilon grows per unit travelled, so far away surfaces aren't
+    // resolved more finely than a pixel. Zero keeps epsilon fixed.
+    pub pixel_cone: f32,
+    // Rays going further than this miss
+    pub max_distance: f32,
+    // Each step is the distance estimate times this, below 1 for worlds that
+    // overestimate their distance
+    pub step_scale: f32,
+    pub strategy: Strategy,
+}
+
+impl MarchSettings {
+    pub fn new() -> MarchSettings {
+        MarchSettings {
+            iterations: 30,
+            epsilon: 1e-2,
+            pixel_cone: 0.,
+            max_distance: 100.,
+            step_scale: 1.,
+            strategy: Strategy::Sphere,
+        }
+    }
+}
+
+// Packets of 16 rays to be marched
+#[derive(Default)]
+pub struct Rays {
+    pub origins: Vec<Vec3dx16>,
+    pub dirs: Vec<Vec3dx16>,
+    // How far along each ray marching starts, known to be before any surface
+    pub starts: Vec<f32x16>,
+    // The time each ray is marched at
+    pub times: Vec<f32x16>,
+}
+
+impl Rays {
+    pub fn push(&mut self, origins: Vec3dx16, dirs: Vec3dx16, starts: f32x16, times: f32x16) {
+        self.origins.push(origins);
+        self.dirs.push(dirs);
+        self.starts.push(starts);
+        self.times.push(times);
+    }
+
+    pub fn len(&self) -> usize {
+        self.origins.len()
+    }
+}
+
+// Where a packet of rays ended up
+#[derive(Debug, Clone, Copy)]
+pub struct Marched {
+    // On the surface for rays that hit
+    pub poses: Vec3dx16,
+    // Normalized
+    pub dirs: Vec3dx16,
+    // Distance travelled along each ray
+    pub depths: f32x16,
+    pub hit: m32x16,
+    // Went further than the max distance
+    pub missed: m32x16,
+    // Ran out of iterations before hitting or missing. With the enhanced
+    // strategy these end where they came closest to a surface.
+    pub exhausted: m32x16,
+}
+
+// Secant steps taken towards the surface after a hit
+const REFINE_STEPS: usize = 2;
+// How far the corners normals are estimated at are from the point
+const NORMAL_OFFSET: f32 = 1e-3;
+// Hits are moved to this distance from the surface rather than onto it, so
+// the corners for the normal are all on the same side even for worlds whose
+// distance doesn't go negative inside
+const SURFACE_OFFSET: f32 = 2. * NORMAL_OFFSET;
+
+// Colors for any number of packets of rays, see `march`
+pub fn raymarch_packets(world: &dyn World, rays: &Rays, settings: &MarchSettings) -> Vec<Vec3dx16> {
+    march(world, rays, settings)
+        .iter()
+        .zip(&rays.times)
+        .map(|(marched, &time)| shade(world, marched, time))
+        .collect()
+}
+
+// Lights surfaces from the camera, so they get darker the more they face
+// away from it. Misses are black, and so are rays that ran out of
+// iterations, which aren't known to be on any surface.
+pub fn shade(world: &dyn World, marched: &Marched, time: f32x16) -> Vec3dx16 {
+    let (_, colors) = world.distance_color(&marched.poses, time);
+    let facing = normal(world, &marched.poses, time)
+        .dot(&marched.dirs)
+        .min(f32x16::splat(0.));
+
+    let light = marched.hit.select(-facing, f32x16::splat(0.));
+    colors * Vec3dx16::splat(light)
+}
+
+// Direction in which the distance grows fastest, evaluated at the corners
+// of a small tetrahedron
+pub fn normal(world: &dyn World, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+    let mut res = Vec3dx16::default();
+    for &(cx, cy, cz) in &[(1., -1., -1.), (-1., -1., 1.), (-1., 1., -1.), (1., 1., 1.)] {
+        let corner = Vec3dx16::from_tuple((cx, cy, cz));
+        let offset = Vec3dx16::from_tuple((cx * NORMAL_OFFSET, cy * NORMAL_OFFSET, cz * NORMAL_OFFSET));
+        let de = world.distance_estimator(&(x + offset), time);
+        res += corner * Vec3dx16::splat(de);
+    }
+
+    res / Vec3dx16::splat(norm(&res).max(f32x16::splat(1e-20)))
+}
+
+// Marches any number of packets of rays. The rays share 16 lanes, and a lane
+// is refilled with the next waiting ray as soon as its ray finishes, so a few
+// slow rays don't keep the rest of the lanes idle.
+pub fn march(world: &dyn World, rays: &Rays, settings: &MarchSettings) -> Vec<Marched> {
+    let count = 16 * rays.len();
+    let dirs: Vec<Vec3dx16> = rays.dirs.iter().map(|d| d / Vec3dx16::splat(norm(d))).collect();
+
+    let none = m32x16::splat(false);
+    let zeros = f32x16::splat(0.);
+
+    let mut res: Vec<Marched> = rays
+        .origins
+        .iter()
+        .zip(&dirs)
+        .map(|(&poses, &dirs)| Marched {
+            poses,
+            dirs,
+            depths: zeros,
+            hit: none,
+            missed: none,
+            exhausted: none,
+        })
+        .collect();
+
+    // For refining hits, the distance where each ray stopped and at the step
+    // before, and how long that step was
+    let mut end_des = vec![zeros; rays.len()];
+    let mut before_des = vec![zeros; rays.len()];
+    let mut before_step = vec![zeros; rays.len()];
+
+    // The ray each lane is marching, as an index into all the rays
+    let mut lanes = [0; 16];
+    let mut active = none;
+    let mut next = 0;
+
+    let (relaxation, candidates) = match settings.strategy {
+        Strategy::Sphere => (1., false),
+        Strategy::OverRelaxed { relaxation } => (relaxation, false),
+        Strategy::Enhanced { relaxation } => (relaxation, true),
+    };
+
+    let mut poses = Vec3dx16::default();
+    let mut lane_dirs = Vec3dx16::default();
+    let mut lane_times = zeros;
+    let mut last_des = zeros;
+    let mut last_step = zeros;
+    let mut travelled = zeros;
+    let mut iterations = zeros;
+    // Drops to 1 for the rest of the ray once an over-relaxed step goes too
+    // far
+    let mut relax = f32x16::splat(relaxation);
+    // The closest the ray came to a surface, relative to how far it went
+    let mut best_error = f32x16::splat(f32::INFINITY);
+    let mut best_poses = Vec3dx16::default();
+    let mut best_travelled = zeros;
+
+    let mut refill = true;
+
+    loop {
+        if refill {
+            for (lane, ray) in lanes.iter_mut().enumerate() {
+                if !active.extract(lane) && next < count {
+                    let (k, i) = (next / 16, next % 16);
+
+                    let (x, y, z) = rays.origins[k].extract(i);
+                    let (dx, dy, dz) = dirs[k].extract(i);
+                    let start = rays.starts[k].extract(i);
+
+                    poses.replace(lane, (x + dx * start, y + dy * start, z + dz * start));
+                    lane_dirs.replace(lane, (dx, dy, dz));
+                    lane_times = lane_times.replace(lane, rays.times[k].extract(i));
+                    last_des = last_des.replace(lane, 0.);
+                    last_step = last_step.replace(lane, 0.);
+                    travelled = travelled.replace(lane, start);
+                    iterations = iterations.replace(lane, 0.);
+                    relax = relax.replace(lane, relaxation);
+                    best_error = best_error.replace(lane, f32::INFINITY);
+
+                    *ray = next;
+                    active = active.replace(lane, true);
+                    next += 1;
+                }
+            }
+            refill = false;
+        }
+
+        if active.none() {
+            break;
+        }
+
+        let mut des = world.distance_estimator(&poses, lane_times);
+
+        // Go back from relaxed steps that may have passed a surface, to where
+        // the distance was last_des
+        let overshot = last_step.gt(last_des + des) & relax.gt(f32x16::splat(1.)) & active;
+        if overshot.any() {
+            let back = overshot.select(last_step, zeros);
+            poses -= lane_dirs * Vec3dx16::splat(back);
+            travelled -= back;
+            des = overshot.select(last_des, des);
+            if !candidates {
+                relax = overshot.select(f32x16::splat(1.), relax);
+            }
+        }
+
+        if candidates {
+            let error = des / travelled.max(f32x16::splat(settings.epsilon));
+            let better = error.lt(best_error) & active;
+            best_error = better.select(error, best_error);
+            best_poses = Vec3dx16::select(better, &poses, &best_poses);
+            best_travelled = better.select(travelled, best_travelled);
+        }
+
+        // Anything within epsilon of a surface, or inside one, is a hit
+        let epsilon = settings.epsilon + travelled * settings.pixel_cone;
+        let hit = des.le(epsilon) & active;
+        let last_iteration = f32x16::splat(settings.iterations.max(1) as f32 - 1.);
+        let exhausted = iterations.ge(last_iteration) & active & !hit;
+        let stopped = hit | exhausted;
+
+        let step = match settings.strategy {
+            Strategy::Sphere | Strategy::OverRelaxed { .. } => des * relax,
+            Strategy::Enhanced { .. } => {
+                // How fast the distance shrinks along the ray. For a plane
+                // the spheres keep overlapping up to 2 des / (1 - slope)
+                let slope = ((des - last_des) / last_step)
+                    .max(f32x16::splat(-1.))
+                    .min(zeros);
+                let planar = (0.9 * 2. * des / (1. - slope)).max(des).min(des * relax);
+
+                // Without a previous step, or just after going back, there is
+                // no slope to go by
+                (last_step.gt(zeros) & !overshot).select(planar, des)
+            }
+        };
+        let step = (active & !stopped).select(step * settings.step_scale, zeros);
+        poses += lane_dirs * Vec3dx16::splat(step);
+        travelled += step;
+        iterations += 1.;
+
+        let missed = travelled.gt(f32x16::splat(settings.max_distance)) & active & !stopped;
+
+        let done = stopped | missed;
+        if done.any() {
+            let (ends, depths) = if candidates {
+                (
+                    Vec3dx16::select(exhausted, &best_poses, &poses),
+                    exhausted.select(best_travelled, travelled),
+                )
+            } else {
+                (poses, travelled)
+            };
+
+            for (lane, &ray) in lanes.iter().enumerate() {
+                if done.extract(lane) {
+                    let (k, i) = (ray / 16, ray % 16);
+                    let marched = &mut res[k];
+
+                    marched.poses.replace(i, ends.extract(lane));
+                    marched.depths = marched.depths.replace(i, depths.extract(lane));
+                    marched.hit = marched.hit.replace(i, hit.extract(lane));
+                    marched.missed = marched.missed.replace(i, missed.extract(lane));
+                    marched.exhausted = marched.exhausted.replace(i, exhausted.extract(lane));
+
+                    end_des[k] = end_des[k].replace(i, des.extract(lane));
+                    before_des[k] = before_des[k].replace(i, last_des.extract(lane));
+                    before_step[k] = before_step[k].replace(i, last_step.extract(lane));
+                }
+            }
+
+            active &= !done;
+            refill = next < count;
+        }
+
+        last_des = des;
+        last_step = step;
+    }
+
+    for (k, marched) in res.iter_mut().enumerate() {
+        if marched.hit.any() {
+            refine(world, marched, end_des[k], before_des[k], before_step[k], rays.times[k]);
+        }
+    }
+
+    res
+}
+
+// Moves hits to SURFACE_OFFSET from the surface, with secant steps towards
+// where the distance along the ray is that. Starts from the distances `des`
+// where the rays stopped and `before_des` one step of `before_step` earlier.
+fn refine(
+    world: &dyn World,
+    marched: &mut Marched,
+    des: f32x16,
+    before_des: f32x16,
+    before_step: f32x16,
+    time: f32x16,
+) {
+    let zeros = f32x16::splat(0.);
+    let offset = f32x16::splat(SURFACE_OFFSET);
+
+    // Positions along the rays relative to where they stopped, and distances
+    // relative to the offset. The offset surface is at least that far away
+    // from any point, so it's known to be between lo and hi.
+    let (mut t0, mut d0) = (-before_step, before_des - offset);
+    let (mut t1, mut d1) = (zeros, des - offset);
+    // Rays that stopped inside the offset surface are only known to be
+    // outside where they started
+    let mut lo = d1.ge(zeros).select(d1, -marched.depths);
+    let mut hi = d1.lt(zeros).select(d1, f32x16::splat(f32::INFINITY));
+    let mut inside = d1.lt(zeros);
+    // Rays that hit at their first step have no earlier point
+    let mut two_points = before_step.gt(zeros);
+    lo = (two_points & d0.gt(zeros)).select(lo.max(t0 + d0), lo);
+
+    for _ in 0..REFINE_STEPS {
+        // Lanes without two different distances take a sphere tracing step.
+        // Until a point inside is found nothing bounds the secant, so it
+        // can't go further than a sphere tracing step either.
+        let secant = d1.ne(d0) & two_points;
+        let cap = inside.select(hi, t1 + d1);
+        let t = secant
+            .select(t1 - d1 * (t1 - t0) / (d1 - d0), t1 + d1)
+            .max(lo)
+            .min(cap);
+
+        let d = world.distance_estimator(&(marched.poses + marched.dirs * Vec3dx16::splat(t)), time)
+            - offset;
+        lo = d.gt(zeros).select(lo.max(t + d), lo);
+        hi = d.lt(zeros).select(hi.min(t + d), hi);
+        inside |= d.lt(zeros);
+
+        t0 = t1;
+        d0 = d1;
+        t1 = t;
+        d1 = d;
+        two_points = m32x16::splat(true);
+    }
+
+    // Where the last step didn't end outside the offset surface, the
+    // furthest point known to be outside is used instead
+    let outside = d1.ge(zeros) & t1.ge(lo) & t1.le(hi);
+    let t1 = outside.select(t1, lo);
+    let t1 = marched.hit.select(t1, zeros);
+    marched.poses += marched.dirs * Vec3dx16::splat(t1);
+    marched.depths += t1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{TransT, Translation, UnitSphere};
+
+    // A sphere of radius 1, 5 in front of the origin
+    fn sphere() -> TransT<UnitSphere> {
+        Translation::new(UnitSphere, (0., 0., 5.))
+    }
+
+    // Marches rays from the origin in one direction per lane
+    fn march_from_origin(dirs: Vec3dx16, settings: &MarchSettings) -> Marched {
+        let mut rays = Rays::default();
+        let zeros = f32x16::splat(0.);
+        rays.push(Vec3dx16::default(), dirs, zeros, zeros);
+        march(&sphere(), &rays, settings)[0]
+    }
+
+    // Spread over the sphere as seen from the origin
+    fn towards_sphere() -> Vec3dx16 {
+        let lanes = (0..16).fold(f32x16::splat(0.), |v, lane| v.replace(lane, lane as f32));
+        let angle = lanes * 0.4;
+        Vec3dx16 { xs: angle.cos() * 0.1, ys: angle.sin() * 0.1, zs: f32x16::splat(1.) }
+    }
+
+    // Exactly one of the outcomes is set in every lane
+    fn assert_outcome(marched: &Marched, hit: bool, missed: bool, exhausted: bool) {
+        for i in 0..16 {
+            assert_eq!(
+                (marched.hit.extract(i), marched.missed.extract(i), marched.exhausted.extract(i)),
+                (hit, missed, exhausted),
+            );
+        }
+    }
+
+    #[test]
+    fn hit() {
+        for &strategy in &[Strategy::Sphere, Strategy::over_relaxed(), Strategy::enhanced()] {
+            let settings = MarchSettings { iterations: 100, strategy, ..MarchSettings::new() };
+            let marched = march_from_origin(towards_sphere(), &settings);
+            assert_outcome(&marched, true, false, false);
+
+            // Just in front of the surface, and lit
+            let distances = sphere().distance_estimator(&marched.poses, f32x16::splat(0.));
+            assert!(distances.ge(f32x16::splat(0.)).all());
+            assert!(distances.le(f32x16::splat(2. * SURFACE_OFFSET)).all());
+            let colors = shade(&sphere(), &marched, f32x16::splat(0.));
+            assert!(colors.xs.gt(f32x16::splat(0.5)).all());
+        }
+    }
+
+    #[test]
+    fn missed() {
+        let settings = MarchSettings { iterations: 100, ..MarchSettings::new() };
+        let away = Vec3dx16 { zs: -towards_sphere().zs, ..towards_sphere() };
+        let marched = march_from_origin(away, &settings);
+        assert_outcome(&marched, false, true, false);
+        assert!(marched.depths.gt(f32x16::splat(settings.max_distance)).all());
+
+        let colors = shade(&sphere(), &marched, f32x16::splat(0.));
+        assert!(colors.xs.eq(f32x16::splat(0.)).all());
+    }
+
+    #[test]
+    fn exhausted() {
+        // Too few steps to get anywhere
+        let settings = MarchSettings { iterations: 2, ..MarchSettings::new() };
+        let marched = march_from_origin(towards_sphere(), &settings);
+        assert_outcome(&marched, false, false, true);
+
+        // Not shaded as whatever is near where they stopped
+        let colors = shade(&sphere(), &marched, f32x16::splat(0.));
+        assert!(colors.xs.eq(f32x16::splat(0.)).all());
+    }
+}
@@ -0,0 +1,299 @@
+use crate::simd::{f32x16, i32x16, u32x16, FromCast};
+
+use std::f32::consts::PI;
+
+use crate::vec::Vec3dx16;
+
+// How often `max_stretch` squares A^T A, the bound is at most 3^(1/2^(n+2))
+// times too large
+const SQUARINGS: u32 = 6;
+
+pub fn floor(x: f32x16) -> f32x16 {
+    let trunc = f32x16::from_cast(i32x16::from_cast(x));
+    trunc.gt(x).select(trunc - 1., trunc)
+}
+
+// Polynomial approximation, max error is around 1e-5 radians
+pub fn atan2(y: f32x16, x: f32x16) -> f32x16 {
+    let ax = x.abs();
+    let ay = y.abs();
+
+    let a = ax.min(ay) / ax.max(ay).max(f32x16::splat(1e-30));
+    let s = a * a;
+    let r = ((-0.046_496_475 * s + 0.159_314_22) * s - 0.327_622_76) * s * a + a;
+
+    let r = ay.gt(ax).select(PI / 2. - r, r);
+    let r = x.lt(f32x16::splat(0.)).select(PI - r, r);
+    y.lt(f32x16::splat(0.)).select(-r, r)
+}
+
+pub fn acos(x: f32x16) -> f32x16 {
+    atan2((1. - x * x).max(f32x16::splat(0.)).sqrt(), x)
+}
+
+// Integer hash (lowbias32), good enough to decorrelate lattice points
+pub fn hash(x: u32x16) -> u32x16 {
+    let x = x ^ (x >> 16);
+    let x = x * 0x7feb_352d;
+    let x = x ^ (x >> 15);
+    let x = x * 0x846c_a68b;
+    x ^ (x >> 16)
+}
+
+// Uniform in [0, 1), a different value for each seed
+pub fn random(seed: u32x16) -> f32x16 {
+    f32x16::from_cast(hash(seed) >> 8) * (1. / (1 << 24) as f32)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quat {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quat {
+    pub fn identity() -> Quat {
+        Quat { w: 1., x: 0., y: 0., z: 0. }
+    }
+
+    pub fn from_axis_angle(axis: (f32, f32, f32), angle: f32) -> Quat {
+        let len = (axis.0 * axis.0 + axis.1 * axis.1 + axis.2 * axis.2).sqrt();
+        assert!(len > 0., "Rotation axis needs to be non-zero");
+        let s = (angle / 2.).sin() / len;
+        Quat { w: (angle / 2.).cos(), x: axis.0 * s, y: axis.1 * s, z: axis.2 * s }
+    }
+
+    pub fn normalized(&self) -> Quat {
+        let len = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        Quat { w: self.w / len, x: self.x / len, y: self.y / len, z: self.z / len }
+    }
+
+    #[allow(dead_code)]
+    pub fn mul(&self, o: &Quat) -> Quat {
+        Quat {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+}
+
+// An affine transform, the last column is the translation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat3x4 {
+    pub rows: [[f32; 4]; 3],
+}
+
+impl Mat3x4 {
+    #[allow(dead_code)]
+    pub fn identity() -> Mat3x4 {
+        Mat3x4 {
+            rows: [
+                [1., 0., 0., 0.],
+                [0., 1., 0., 0.],
+                [0., 0., 1., 0.],
+            ],
+        }
+    }
+
+    // Rotation, then uniform scaling, then translation
+    pub fn from_parts(rotation: Quat, translation: (f32, f32, f32), scale: f32) -> Mat3x4 {
+        let Quat { w, x, y, z } = rotation.normalized();
+        Mat3x4 {
+            rows: [
+                [
+                    scale * (1. - 2. * (y * y + z * z)),
+                    scale * 2. * (x * y - w * z),
+                    scale * 2. * (x * z + w * y),
+                    translation.0,
+                ],
+                [
+                    scale * 2. * (x * y + w * z),
+                    scale * (1. - 2. * (x * x + z * z)),
+                    scale * 2. * (y * z - w * x),
+                    translation.1,
+                ],
+                [
+                    scale * 2. * (x * z - w * y),
+                    scale * 2. * (y * z + w * x),
+                    scale * (1. - 2. * (x * x + y * y)),
+                    translation.2,
+                ],
+            ],
+        }
+    }
+
+    // self * o, ie. applies o first
+    #[allow(dead_code)]
+    pub fn mul(&self, o: &Mat3x4) -> Mat3x4 {
+        let mut rows = [[0.; 4]; 3];
+        for (row, own) in rows.iter_mut().zip(&self.rows) {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..3).map(|k| own[k] * o.rows[k][j]).sum();
+            }
+            row[3] += own[3];
+        }
+        Mat3x4 { rows }
+    }
+
+    pub fn inverse(&self) -> Mat3x4 {
+        let m = &self.rows;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+
+        let adj = [
+            [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+            [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+            [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+        ];
+        let det = m[0][0] * adj[0][0] + m[0][1] * adj[1][0] + m[0][2] * adj[2][0];
+
+        let mut rows = [[0.; 4]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                rows[i][j] = adj[i][j] / det;
+            }
+            rows[i][3] = -(0..3).map(|k| rows[i][k] * m[k][3]).sum::<f32>();
+        }
+        Mat3x4 { rows }
+    }
+
+    // An upper bound on how much the linear part A can lengthen a vector.
+    // The largest eigenvalue of B = A^T A is at most the Frobenius norm of
+    // B^n to the 1/n, which gets close to it as n grows, so B is squared a
+    // few times. Each power is normalized, with the factors kept in `log`.
+    pub fn max_stretch(&self) -> f32 {
+        let m = &self.rows;
+        let mut b = [[0f64; 3]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                b[i][j] = (0..3).map(|k| m[k][i] as f64 * m[k][j] as f64).sum();
+            }
+        }
+
+        let frobenius = |b: &[[f64; 3]; 3]| b.iter().flatten().map(|v| v * v).sum::<f64>().sqrt();
+        let mut log = 0.;
+        for _ in 0..SQUARINGS {
+            let norm = frobenius(&b);
+            if norm == 0. {
+                return 0.;
+            }
+            log += norm.ln();
+            let mut sq = [[0f64; 3]; 3];
+            for i in 0..3 {
+                for j in 0..3 {
+                    sq[i][j] = (0..3).map(|k| b[i][k] * b[k][j]).sum::<f64>() / (norm * norm);
+                }
+            }
+            b = sq;
+            log *= 2.;
+        }
+
+        // A^T A to the power 2^SQUARINGS is exp(log) * b, the stretch is the
+        // square root of its 2^SQUARINGS-th root
+        let power = (1u32 << SQUARINGS) as f64;
+        // Rounding to f32 mustn't make the bound smaller
+        let stretch = ((log + frobenius(&b).ln()) / (2. * power)).exp();
+        (stretch * (1. + 1e-6)) as f32
+    }
+
+    pub fn apply_vector(&self, v: (f32, f32, f32)) -> (f32, f32, f32) {
+        let m = &self.rows;
+        (
+            m[0][0] * v.0 + m[0][1] * v.1 + m[0][2] * v.2,
+            m[1][0] * v.0 + m[1][1] * v.1 + m[1][2] * v.2,
+            m[2][0] * v.0 + m[2][1] * v.1 + m[2][2] * v.2,
+        )
+    }
+
+    pub fn apply_point(&self, p: (f32, f32, f32)) -> (f32, f32, f32) {
+        let v = self.apply_vector(p);
+        (v.0 + self.rows[0][3], v.1 + self.rows[1][3], v.2 + self.rows[2][3])
+    }
+
+    pub fn apply(&self, x: &Vec3dx16) -> Vec3dx16 {
+        let row = |r: &[f32; 4]| x.xs * r[0] + x.ys * r[1] + x.zs * r[2] + r[3];
+        Vec3dx16 {
+            xs: row(&self.rows[0]),
+            ys: row(&self.rows[1]),
+            zs: row(&self.rows[2]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: &Mat3x4, b: &Mat3x4) {
+        for i in 0..3 {
+            for j in 0..4 {
+                assert!((a.rows[i][j] - b.rows[i][j]).abs() < 1e-4, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_transform() {
+        let transforms = [
+            Mat3x4::identity(),
+            Mat3x4::from_parts(Quat::from_axis_angle((0., 1., 0.), 0.8), (1., -2., 3.), 1.),
+            Mat3x4::from_parts(Quat::from_axis_angle((1., 2., -0.5), 2.5), (-4., 0.5, 7.), 0.3),
+            // Shear and non uniform scaling, which from_parts can't make
+            Mat3x4 {
+                rows: [
+                    [2., 0.5, 0., 1.],
+                    [0., 1., 0.25, -3.],
+                    [0.1, 0., 4., 2.],
+                ],
+            },
+        ];
+
+        for m in &transforms {
+            let inv = m.inverse();
+            assert_near(&m.mul(&inv), &Mat3x4::identity());
+            assert_near(&inv.mul(m), &Mat3x4::identity());
+
+            let p = (0.3, -1.7, 2.2);
+            let q = inv.apply_point(m.apply_point(p));
+            assert!((q.0 - p.0).abs() < 1e-4 && (q.1 - p.1).abs() < 1e-4 && (q.2 - p.2).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn max_stretch_bounds_every_vector() {
+        let shear = Mat3x4 {
+            rows: [
+                [2., 0.5, 0., 1.],
+                [0., 1., 0.25, -3.],
+                [0.1, 0., 4., 2.],
+            ],
+        };
+        let rotation = Mat3x4::from_parts(Quat::from_axis_angle((1., 2., -0.5), 2.5), (-4., 0.5, 7.), 0.3);
+
+        for m in &[shear, shear.inverse(), rotation, rotation.inverse()] {
+            let stretch = m.max_stretch();
+            let mut largest = 0f32;
+            for i in 0..2000 {
+                let (a, b) = (i as f32 * 0.618_034, i as f32 * 0.002_5);
+                let v = (a.cos() * b.sin(), a.sin() * b.sin(), b.cos());
+                let w = m.apply_vector(v);
+                largest = largest.max((w.0 * w.0 + w.1 * w.1 + w.2 * w.2).sqrt());
+            }
+            assert!(largest <= stretch && stretch < largest * 1.01, "{} {}", largest, stretch);
+        }
+
+        // Rotations only stretch by their scale
+        assert!((rotation.max_stretch() - 0.3).abs() < 0.3 * 0.005);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_rotation_axis() {
+        Quat::from_axis_angle((0., 0., 0.), 1.);
+    }
+}
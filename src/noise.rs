@@ -0,0 +1,142 @@
+use crate::simd::{f32x16, i32x16, u32x16, FromCast};
+
+use crate::math::{floor, hash};
+use crate::vec::Vec3dx16;
+
+// The largest gradient length of `perlin` over every choice of corner
+// gradients, found by maximizing over the position in a cell and the
+// direction. It's reached in the middle of a cell, where each of the eight
+// corners adds fade'(1/2) / 4 = 0.46875.
+const PERLIN_LIPSCHITZ: f32 = 3.75;
+// The largest value of `perlin` found the same way, about 1.0363
+const PERLIN_BOUND: f32 = 1.04;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    #[allow(dead_code)]
+    // sin(x) * sin(y) * sin(z)
+    Sine,
+    Perlin,
+    #[allow(dead_code)]
+    Fbm { octaves: u32, lacunarity: f32, gain: f32 },
+}
+
+impl Field {
+    pub fn sample(&self, x: &Vec3dx16) -> f32x16 {
+        match *self {
+            Field::Sine => x.xs.sin() * x.ys.sin() * x.zs.sin(),
+            Field::Perlin => perlin(x),
+            Field::Fbm { octaves, lacunarity, gain } => {
+                let mut res = f32x16::splat(0.);
+                let mut x = *x;
+                let mut amplitude = 1.;
+                for _ in 0..octaves {
+                    res += perlin(&x) * amplitude;
+                    x *= Vec3dx16::from_tuple((lacunarity, lacunarity, lacunarity));
+                    amplitude *= gain;
+                }
+                res
+            }
+        }
+    }
+
+    // Upper bound on the absolute value of `sample`
+    pub fn bound(&self) -> f32 {
+        match *self {
+            Field::Sine => 1.,
+            Field::Perlin => PERLIN_BOUND,
+            Field::Fbm { octaves, gain, .. } => {
+                (0..octaves).map(|i| gain.abs().powi(i as i32)).sum::<f32>() * PERLIN_BOUND
+            }
+        }
+    }
+
+    // Upper bound on the gradient length of `sample`
+    pub fn lipschitz(&self) -> f32 {
+        match *self {
+            Field::Sine => 1.,
+            Field::Perlin => PERLIN_LIPSCHITZ,
+            Field::Fbm { octaves, lacunarity, gain } => {
+                (0..octaves).map(|i| (lacunarity * gain).powi(i as i32)).sum::<f32>()
+                    * PERLIN_LIPSCHITZ
+            }
+        }
+    }
+}
+
+fn fade(t: f32x16) -> f32x16 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f32x16, b: f32x16, t: f32x16) -> f32x16 {
+    a + (b - a) * t
+}
+
+// Ken Perlin's gradient selection, picks one of the 12 cube edge directions
+fn grad(h: u32x16, x: f32x16, y: f32x16, z: f32x16) -> f32x16 {
+    let h = h & 15;
+    let u = h.lt(u32x16::splat(8)).select(x, y);
+    let v = h.lt(u32x16::splat(4)).select(
+        y,
+        (h.eq(u32x16::splat(12)) | h.eq(u32x16::splat(14))).select(x, z),
+    );
+
+    (h & 1).eq(u32x16::splat(0)).select(u, -u) + (h & 2).eq(u32x16::splat(0)).select(v, -v)
+}
+
+// Classic gradient noise, roughly in [-1, 1]
+pub fn perlin(x: &Vec3dx16) -> f32x16 {
+    let (fx, fy, fz) = (floor(x.xs), floor(x.ys), floor(x.zs));
+    let ix = u32x16::from_cast(i32x16::from_cast(fx));
+    let iy = u32x16::from_cast(i32x16::from_cast(fy));
+    let iz = u32x16::from_cast(i32x16::from_cast(fz));
+    let (tx, ty, tz) = (x.xs - fx, x.ys - fy, x.zs - fz);
+
+    let corner = |dx: u32, dy: u32, dz: u32| {
+        let h = hash(hash(hash(ix + dx) + iy + dy) + iz + dz);
+        grad(h, tx - dx as f32, ty - dy as f32, tz - dz as f32)
+    };
+
+    let (u, v, w) = (fade(tx), fade(ty), fade(tz));
+
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::random;
+
+    // Finite differences over a lot of cells never exceed the bounds
+    #[test]
+    fn perlin_within_bounds() {
+        let h = 1e-2;
+        let lanes = (0..16).fold(u32x16::splat(0), |v, lane| v.replace(lane, lane as u32));
+        for seed in 0..2000u32 {
+            let coord = |k: u32| random(lanes * 3 + k + seed * 48) * 40. - 20.;
+            let x = Vec3dx16 { xs: coord(0), ys: coord(1), zs: coord(2) };
+            let value = perlin(&x);
+
+            let diff = |d: (f32, f32, f32)| (perlin(&(x + Vec3dx16::from_tuple(d))) - value) / h;
+            let (gx, gy, gz) = (diff((h, 0., 0.)), diff((0., h, 0.)), diff((0., 0., h)));
+            let gradient = (gx * gx + gy * gy + gz * gz).sqrt();
+
+            for lane in 0..16 {
+                assert!(value.extract(lane).abs() <= PERLIN_BOUND);
+                assert!(gradient.extract(lane) <= PERLIN_LIPSCHITZ, "{}", gradient.extract(lane));
+            }
+        }
+    }
+}
@@ -1,16 +1,14 @@
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Scancode;
 use sdl2::mouse::MouseUtil;
-use packed_simd::{u8x16, FromCast};
+use crate::simd::{f32x16, m32x16, u32x16, u8x16, FromCast};
 
-use std::cell::Cell;
-use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::thread::sleep;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::cell::RefCell;
+use std::sync::Mutex;
 
 use rayon::prelude::*;
 
@@ -18,112 +16,309 @@ use std::f32::consts::PI;
 
 use ytesrev::prelude::*;
 
+use crate::camera::{Camera, Projection, Stereo, StereoMode};
+use crate::framebuffer::Framebuffer;
+use crate::framebuffer::Tile;
+use crate::march::{raymarch_packets, MarchSettings, Rays, Strategy};
+use crate::math::{hash, random};
+use crate::scenes::{self, SceneSettings};
 use crate::vec::Vec3dx16;
 use crate::world::*;
 
 const SPEED: f32 = 0.7;
 
-pub static mut MOUSE: Option<MouseUtil> = None;
+// Only touched from the thread running the window
+thread_local! {
+    pub static MOUSE: RefCell<Option<MouseUtil>> = const { RefCell::new(None) };
+}
 
 pub struct Renderer {
-    data: Vec<u8>,
-    rotation_x: f32,
-    rotation_y: f32,
-    world: Box<Union>,
-    pos: (f32, f32, f32),
+    // RGBA, `width` by `height` pixels. Rendered when it's drawn, so that
+    // changes in between are only rendered once.
+    data: Mutex<Vec<u8>>,
+    // Whether anything changed since `data` was rendered
+    stale: AtomicBool,
+    width: usize,
+    height: usize,
+    camera: Camera,
+    world: Box<dyn World>,
+    // What `world` was built from, the keys change it and rebuild the world
+    scene: SceneSettings,
+    // Relative to the camera, see `Camera::to_world`
     vel: (f32, f32, f32),
-    center_mouse: Cell<bool>,
+    // Rays per pixel, averaged together
+    samples: usize,
+    // Seconds since start, what the world is rendered at
+    time: f32,
+    // Each sample is taken at a random time this many seconds before `time`,
+    // giving motion blur
+    shutter: f32,
+    stereo: Stereo,
+    layout: PacketLayout,
+    // Whether finished lanes are refilled with rays from the rest of the
+    // tile, see `raymarch_packets`
+    compact: bool,
+    // Whether rays start at a depth found by marching cones around groups of
+    // them, see `cone_starts`
+    prepass: bool,
+    // Its `max_distance` is replaced by the camera's far distance, see
+    // `frame`
+    march_settings: MarchSettings,
+    // Atomic rather than a Cell so render workers can share &self
+    center_mouse: AtomicBool,
 }
 
-pub const SIZE: usize = 1000;
-const THREADS: usize = 8;
+// How the 16 pixels traced together are arranged on screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PacketLayout {
+    // 16 by 1
+    Row,
+    // 4 by 4, neighbouring rays tend to finish marching at the same time
+    Block,
+}
 
-impl Renderer {
-    pub fn new() -> Renderer {
-        let cube = construct_cuboid((1., -2., 5.), (0.5, 0.5, 0.5));
-        let cube = Coloring::new(cube, (0., 1., 0.));
+impl PacketLayout {
+    fn size(self) -> (usize, usize) {
+        match self {
+            PacketLayout::Row => (16, 1),
+            PacketLayout::Block => (4, 4),
+        }
+    }
+}
 
-        let sphere = construct_sphere((-4., 0., 7.), 1.);
-        let sphere = Checkers::new(sphere, (0., 0., 0.), (1., 1., 0.));
+// Packets of rays waiting to be marched. Each adds its color, times its
+// weight, to one packet of pixels
+#[derive(Default)]
+struct Queue {
+    rays: Rays,
+    weights: Vec<Vec3dx16>,
+    pixels: Vec<usize>,
+}
 
-        // let cubesphere = Box::new(Intersection {
-        //     objects: vec![cube, sphere],
-        // });
+// What every packet of a frame is traced with
+struct Frame {
+    width: usize,
+    height: usize,
+    // Shaped like the image
+    camera: Camera,
+    settings: MarchSettings,
+    // What the world is rendered at
+    time: f32,
+    // Rays per pixel
+    samples: usize,
+}
 
-        let ground = Plane { height: -10. };
-        let ground = Checkers::new(ground, (0., 0., 0.), (1., 1., 0.));
+// 16 pixels traced together
+struct Packet {
+    pxs: f32x16,
+    pys: f32x16,
+    // How far along their rays marching can start
+    starts: f32x16,
+    // Which packet of pixels the rays add their color to
+    index: usize,
+}
 
-        let roof = Plane { height: 10. };
-        let roof = Coloring::new(roof, (0.57, 0.31, 0.07));
+pub const DEFAULT_SIZE: (usize, usize) = (1000, 1000);
+// Side of the square tiles the image is split into for scheduling
+const TILE: usize = 32;
+// Side of the squares that share a starting depth from the cone pre-pass. A
+// tile holds 4 by 4 of them, one per lane.
+const CONE_TILE: usize = TILE / 4;
 
-        let world = Box::new(Union {
-            objects: vec![Box::new(cube), Box::new(sphere), Box::new(ground), Box::new(roof)],
-        });
+const INTERACTIVE_SAMPLES: usize = 1;
+// Used while the scene is held still with Return, for anti aliasing and
+// depth of field
+const STILL_SAMPLES: usize = 16;
+
+impl Renderer {
+    pub fn new(width: usize, height: usize) -> Renderer {
+        let scene = SceneSettings::new(scenes::Scene::Default);
+
+        let camera = Camera::new((0., 0., 0.), FOV_DEG / 180. * PI, width as f32 / height as f32);
 
         Renderer {
-            data: vec![0; (4 * SIZE * SIZE) as usize],
-            world,
-            rotation_x: 0.,
-            rotation_y: 0.,
-            pos: (0., 0., 0.),
+            data: Mutex::new(vec![0; 4 * width * height]),
+            stale: AtomicBool::new(true),
+            width,
+            height,
+            world: scene.build(),
+            scene,
+            camera,
             vel: (0., 0., 0.),
-            center_mouse: Cell::new(false)
+            samples: INTERACTIVE_SAMPLES,
+            time: 0.,
+            shutter: 0.05,
+            stereo: Stereo {
+                mode: StereoMode::Mono,
+                eye_separation: 0.2,
+                convergence: 5.,
+            },
+            layout: PacketLayout::Block,
+            compact: true,
+            prepass: true,
+            march_settings: MarchSettings::new(),
+            center_mouse: AtomicBool::new(false)
+        }
+    }
+
+    pub fn set_world(&mut self, world: Box<dyn World>) {
+        self.world = world;
+    }
+
+    pub fn set_scene(&mut self, scene: SceneSettings) {
+        self.scene = scene;
+        self.world = scene.build();
+    }
+
+    // Rays per pixel
+    pub fn set_samples(&mut self, samples: usize) {
+        self.samples = samples.max(1);
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    // How long each frame is exposed for, in seconds, 0 for no motion blur
+    pub fn set_shutter(&mut self, shutter: f32) {
+        self.shutter = shutter.max(0.);
+    }
+
+    pub fn set_layout(&mut self, layout: PacketLayout) {
+        self.layout = layout;
+    }
+
+    pub fn set_compact(&mut self, compact: bool) {
+        self.compact = compact;
+    }
+
+    pub fn set_prepass(&mut self, prepass: bool) {
+        self.prepass = prepass;
+    }
+
+    pub fn march_settings_mut(&mut self) -> &mut MarchSettings {
+        &mut self.march_settings
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    pub fn stereo_mut(&mut self) -> &mut Stereo {
+        &mut self.stereo
+    }
+
+    // Radius of the lens, 0 for a pinhole camera
+    pub fn set_aperture(&mut self, aperture: f32) {
+        self.camera.aperture = aperture.max(0.);
+    }
+
+    // Distance that is in focus when the aperture isn't 0
+    pub fn set_focus_distance(&mut self, distance: f32) {
+        self.camera.focus_distance = distance;
+    }
+
+    // How far rays go before they miss
+    pub fn set_far(&mut self, far: f32) {
+        self.camera.far = far;
+    }
+
+    // The state a `width` by `height` frame is rendered with. Rays end at
+    // the camera's far distance.
+    fn frame(&self, width: usize, height: usize) -> Frame {
+        let mut camera = self.camera.clone();
+        camera.aspect = width as f32 / height as f32;
+        Frame {
+            width,
+            height,
+            settings: MarchSettings { max_distance: camera.far, ..self.march_settings },
+            camera,
+            time: self.time,
+            samples: self.samples,
+        }
+    }
+
+    // Whether the hit epsilon grows with the width of a pixel
+    pub fn set_pixel_cone(&mut self, on: bool) {
+        self.march_settings.pixel_cone = if on { self.pixel_cone() } else { 0. };
+    }
+
+    // Width of a pixel one unit in front of the camera
+    fn pixel_cone(&self) -> f32 {
+        2. * (self.camera.vfov / 2.).tan() / self.height as f32
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.width = width;
+        self.height = height;
+        *self.data.get_mut().expect("A render panicked") = vec![0; 4 * width * height];
+        *self.stale.get_mut() = true;
+        self.camera.aspect = width as f32 / height as f32;
+
+        if self.march_settings.pixel_cone != 0. {
+            self.march_settings.pixel_cone = self.pixel_cone();
         }
     }
 }
 
 const FOV_DEG: f32 = 45.;
-const FOV_RAD: f32 = FOV_DEG / 360. * PI;
 
 impl Scene for Renderer {
     fn update(&mut self, dt: f64) {
         let dt = dt as f32;
+        self.time += dt;
 
-        // Rotate around self.rotation_y
-        let mut vel_ = self.vel.clone();
-        vel_.1 = self.vel.1 * self.rotation_y.cos() - self.vel.2 * self.rotation_y.sin();
-        vel_.2 = self.vel.1 * self.rotation_y.sin() + self.vel.2 * self.rotation_y.cos();
-
-        // Rotate around self.rotation_x
-        let mut vel__ = vel_.clone();
-        vel__.0 = vel_.0 * self.rotation_x.cos() - vel_.2 * self.rotation_x.sin();
-        vel__.2 = vel_.0 * self.rotation_x.sin() + vel_.2 * self.rotation_x.cos();
+        let vel = self.camera.to_world(self.vel);
 
+        self.camera.position.0 += vel.0 * dt;
+        self.camera.position.1 += vel.1 * dt;
+        self.camera.position.2 += vel.2 * dt;
 
-        self.pos.0 += vel__.0 * dt;
-        self.pos.1 += vel__.1 * dt;
-        self.pos.2 += vel__.2 * dt;
+        *self.stale.get_mut() = true;
     }
 
     fn draw(&self, canvas: &mut Canvas<Window>, _settings: DrawSettings) {
-        self.render();
-
         let creator = canvas.texture_creator();
         let mut texture = creator
-            .create_texture_target(Some(PixelFormatEnum::ABGR8888), SIZE as u32, SIZE as u32)
+            .create_texture_target(
+                Some(PixelFormatEnum::ABGR8888),
+                self.width as u32,
+                self.height as u32,
+            )
             .expect("Can't make texture");
 
         // texture.set_blend_mode(BlendMode::Blend);
 
+        let mut data = self.data.lock().expect("A render panicked");
+        // Also the first frame, which ytesrev draws before any update
+        if self.stale.swap(false, Ordering::SeqCst) {
+            self.render_into(&mut Framebuffer::new(&mut data, self.width, self.height));
+        }
+
         texture
-            .update(None, self.data.as_slice(), 4 * SIZE)
+            .update(None, data.as_slice(), 4 * self.width)
             .expect("Can't update");
 
+        // Stretched over the window until the resize event has arrived
         let (w, h) = canvas.window().size();
 
-        let rect = Rect::new(0, 0, SIZE as u32, SIZE as u32);
+        let rect = Rect::new(0, 0, w, h);
 
         canvas.copy(&texture, None, rect).expect("Can't copy");
 
-        if self.center_mouse.replace(false) {
-            unsafe {
-                let (w, h) = canvas.window().size();
-                if let Some(mouse) = &MOUSE {
+        if self.center_mouse.swap(false, Ordering::SeqCst) {
+            let (w, h) = canvas.window().size();
+            MOUSE.with(|mouse| {
+                if let Some(mouse) = &*mouse.borrow() {
                     mouse.warp_mouse_in_window(canvas.window(), w as i32 / 2, h as i32 / 2);
                     mouse.show_cursor(false);
                     mouse.set_relative_mouse_mode(true);
                 }
-            }
+            });
         }
 
     }
@@ -131,18 +326,141 @@ impl Scene for Renderer {
     fn event(&mut self, event: YEvent) {
         match event {
             YEvent::Other(Event::MouseMotion { xrel, yrel, .. } ) => {
-                self.rotation_x -= xrel as f32 * 0.005;
-                self.rotation_y += yrel as f32 * 0.005;
-                self.center_mouse.set(true);
+                self.camera.yaw += xrel as f32 * 0.005;
+                self.camera.pitch = (self.camera.pitch - yrel as f32 * 0.005).clamp(-PI / 2., PI / 2.);
+                *self.center_mouse.get_mut() = true;
+            }
+            YEvent::Other(Event::Window { win_event: WindowEvent::Resized(w, h), .. } ) => {
+                self.resize(w.max(0) as usize, h.max(0) as usize);
             }
             YEvent::Other(Event::KeyDown { scancode: Some(Scancode::W), .. } ) => {
-                self.vel.2 = -SPEED;
+                self.vel.2 = SPEED;
             }
             YEvent::Other(Event::KeyUp { scancode: Some(Scancode::W), .. } ) => {
                 self.vel.2 = 0.;
             }
-            _ => {}
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::P), .. } ) => {
+                self.camera.projection = match self.camera.projection {
+                    Projection::Perspective => Projection::Orthographic { height: 10. },
+                    Projection::Orthographic { .. } => Projection::Fisheye,
+                    Projection::Fisheye => Projection::Equirectangular,
+                    Projection::Equirectangular => Projection::Cubemap,
+                    Projection::Cubemap => Projection::Perspective,
+                };
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::L), .. } ) => {
+                self.layout = match self.layout {
+                    PacketLayout::Row => PacketLayout::Block,
+                    PacketLayout::Block => PacketLayout::Row,
+                };
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::C), .. } ) => {
+                self.compact = !self.compact;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::X), .. } ) => {
+                self.prepass = !self.prepass;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Up), .. } ) => {
+                self.march_settings.iterations += 5;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Down), .. } ) => {
+                self.march_settings.iterations = self.march_settings.iterations.max(10) - 5;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Right), .. } ) => {
+                self.march_settings.epsilon *= 2.;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Left), .. } ) => {
+                self.march_settings.epsilon /= 2.;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::PageUp), .. } ) => {
+                self.camera.far *= 2.;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::PageDown), .. } ) => {
+                self.camera.far /= 2.;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Equals), .. } ) => {
+                self.march_settings.step_scale = (self.march_settings.step_scale + 0.1).min(1.);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Minus), .. } ) => {
+                self.march_settings.step_scale = (self.march_settings.step_scale - 0.1).max(0.1);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::T), .. } ) => {
+                self.march_settings.strategy = match self.march_settings.strategy {
+                    Strategy::Sphere => Strategy::over_relaxed(),
+                    Strategy::OverRelaxed { .. } => Strategy::enhanced(),
+                    Strategy::Enhanced { .. } => Strategy::Sphere,
+                };
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::K), .. } ) => {
+                let on = self.march_settings.pixel_cone == 0.;
+                self.set_pixel_cone(on);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::O), .. } ) => {
+                self.set_aperture(self.camera.aperture + 0.02);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::I), .. } ) => {
+                self.set_aperture(self.camera.aperture - 0.02);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::G), .. } ) => {
+                self.camera.focus_distance *= 1.25;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::F), .. } ) => {
+                self.camera.focus_distance /= 1.25;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::V), .. } ) => {
+                self.stereo.mode = match self.stereo.mode {
+                    StereoMode::Mono => StereoMode::SideBySide,
+                    StereoMode::SideBySide => StereoMode::Anaglyph,
+                    StereoMode::Anaglyph => StereoMode::Mono,
+                };
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Apostrophe), .. } ) => {
+                self.stereo.eye_separation *= 1.25;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Semicolon), .. } ) => {
+                self.stereo.eye_separation /= 1.25;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Num0), .. } ) => {
+                self.stereo.convergence *= 1.25;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Num9), .. } ) => {
+                self.stereo.convergence /= 1.25;
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::N), .. } ) => {
+                let mut scene = self.scene;
+                scene.next_scene();
+                self.set_scene(scene);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::RightBracket), .. } ) => {
+                let mut scene = self.scene;
+                scene.change_detail(true);
+                self.set_scene(scene);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::LeftBracket), .. } ) => {
+                let mut scene = self.scene;
+                scene.change_detail(false);
+                self.set_scene(scene);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Period), .. } ) => {
+                let mut scene = self.scene;
+                scene.change_shape(true);
+                self.set_scene(scene);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Comma), .. } ) => {
+                let mut scene = self.scene;
+                scene.change_shape(false);
+                self.set_scene(scene);
+            }
+            YEvent::Other(Event::KeyDown { scancode: Some(Scancode::Return), .. } ) => {
+                self.samples = if self.samples == STILL_SAMPLES {
+                    INTERACTIVE_SAMPLES
+                } else {
+                    STILL_SAMPLES
+                };
+            }
+            _ => return,
         }
+        *self.stale.get_mut() = true;
     }
 
     fn action(&self) -> Action {
@@ -155,96 +473,327 @@ impl Scene for Renderer {
 }
 
 impl Renderer {
-    fn render(&self) {
-        let dones = Arc::new(AtomicUsize::new(0));
-
-        let world_translated: TransRef<Union>
-                = Translation::new(&*self.world, self.pos);
-
-        let world_rotated_y = Rotation::new(world_translated, Axis::Y, self.rotation_x);
-
-        let world_rotated_x = Rotation::new(world_rotated_y, Axis::X, self.rotation_y);
-
-        let world = Arc::new(world_rotated_x);
-
-
-        (0..THREADS).into_par_iter().for_each({
-            let ptr: *mut u8 = self.data.as_ptr() as *mut u8;
-            let aptr = AtomicPtr::new(ptr);
-
-            let dones = dones.clone();
-            let world = world.clone();
-
-            move |n| {
-                let ptr = aptr.load(Ordering::SeqCst);
-
-                let start = n * SIZE / THREADS;
-                let end = (n + 1) * SIZE / THREADS;
-
-                let mut curr_dirs = Vec3dx16::default();
-
-                let mut idx = 0;
-
-                for y in 0..SIZE {
-                    for x in start..end {
-                        let xf = x as f32 / (SIZE - 1) as f32 * 2. - 1.;
-                        let yf = y as f32 / (SIZE - 1) as f32 * 2. - 1.;
-
-                        let xrad = xf * FOV_RAD;
-                        let yrad = -yf * FOV_RAD;
-
-                        let dir: (f32, f32, f32) = (
-                            yrad.cos() * xrad.sin(),
-                            yrad.sin(),
-                            yrad.cos() * xrad.cos(),
-                        );
-
-                        curr_dirs.xs = curr_dirs.xs.replace(idx, dir.0);
-                        curr_dirs.ys = curr_dirs.ys.replace(idx, dir.1);
-                        curr_dirs.zs = curr_dirs.zs.replace(idx, dir.2);
-
-                        idx += 1;
-
-                        if idx == 16 {
-                            idx = 0;
-                            let start_v = Vec3dx16::from_tuple((0., 0., 0.));
-                            let resCols = raymarch(&*world, start_v, curr_dirs);
-                            let re = u8x16::from_cast(255. * resCols.xs);
-                            let gr = u8x16::from_cast(255. * resCols.ys);
-                            let bl = u8x16::from_cast(255. * resCols.zs);
-
-                            for i in 0..16 {
-                                let y_;
-                                let x_;
-                                if x + i < start + 15 {
-                                    y_ = y - 1;
-                                    x_ = x + i - 15 + end - start;
-                                } else {
-                                    y_ = y;
-                                    x_ = x - 15 + i;
-                                }
-
-                                unsafe {
-                                    *(ptr.offset(4 * (x_ + y_ * SIZE) as isize + 0)) =
-                                        re.extract(i);
-                                    *(ptr.offset(4 * (x_ + y_ * SIZE) as isize + 1)) =
-                                        gr.extract(i);
-                                    *(ptr.offset(4 * (x_ + y_ * SIZE) as isize + 2)) =
-                                        bl.extract(i);
-                                    *(ptr.offset(4 * (x_ + y_ * SIZE) as isize + 3)) = 255;
-                                }
-                            }
-                        }
+    // Queues the rays for a packet of pixels
+    fn shade(&self, frame: &Frame, packet: &Packet, queue: &mut Queue) {
+        let (width, height) = (frame.width, frame.height);
+        let gray = (1., 1., 1.);
+
+        match self.stereo.mode {
+            StereoMode::Mono => {
+                let camera = &frame.camera;
+                self.trace(frame, packet, gray, queue, |us, vs, lens| {
+                    let (origins, dirs) = camera.rays(us, vs, lens);
+                    (origins, dirs, camera.covers(us, vs))
+                })
+            }
+            StereoMode::SideBySide => {
+                // The right eye gets the extra column of odd widths
+                let left_width = (width / 2) as f32;
+                let right_width = (width - width / 2) as f32;
+
+                let (mut left, mut right) = frame.camera.eyes(&self.stereo);
+                left.aspect = left_width / height as f32;
+                right.aspect = right_width / height as f32;
+
+                let in_right = packet.pxs.ge(f32x16::splat(left_width));
+
+                self.trace(frame, packet, gray, queue, |us, vs, lens| {
+                    // From the whole image to each eye's half
+                    let xs = (us + 1.) / 2. * width as f32;
+                    let lus = xs / left_width.max(1.) * 2. - 1.;
+                    let rus = (xs - left_width) / right_width * 2. - 1.;
+
+                    let (lorigins, ldirs) = left.rays(lus, vs, lens);
+                    let (rorigins, rdirs) = right.rays(rus, vs, lens);
+                    (
+                        Vec3dx16::select(in_right, &rorigins, &lorigins),
+                        Vec3dx16::select(in_right, &rdirs, &ldirs),
+                        (in_right & right.covers(rus, vs)) | (!in_right & left.covers(lus, vs)),
+                    )
+                })
+            }
+            StereoMode::Anaglyph => {
+                let (left, right) = frame.camera.eyes(&self.stereo);
+
+                // Red from the left eye, green and blue from the right
+                let (red, cyan) = ((1., 0., 0.), (0., 1., 1.));
+                self.trace(frame, packet, red, queue, |us, vs, lens| {
+                    let (origins, dirs) = left.rays(us, vs, lens);
+                    (origins, dirs, left.covers(us, vs))
+                });
+                self.trace(frame, packet, cyan, queue, |us, vs, lens| {
+                    let (origins, dirs) = right.rays(us, vs, lens);
+                    (origins, dirs, right.covers(us, vs))
+                });
+            }
+        }
+    }
+
+    // Queues the samples for a packet of pixels, with rays generated by
+    // `gen(us, vs, lens)`, which also says which of them the camera covers.
+    // Together they add `weight` to the color of the covered pixels, the rest
+    // stay black.
+    fn trace<F>(
+        &self,
+        frame: &Frame,
+        packet: &Packet,
+        weight: (f32, f32, f32),
+        queue: &mut Queue,
+        gen: F,
+    )
+        where F: Fn(f32x16, f32x16, (f32x16, f32x16)) -> (Vec3dx16, Vec3dx16, m32x16)
+    {
+        let (pxs, pys) = (packet.pxs, packet.pys);
+        let (width, height) = (frame.width, frame.height);
+        let seeds = hash(u32x16::from_cast(pys) * width as u32 + u32x16::from_cast(pxs));
+
+        let vel = Vec3dx16::from_tuple(frame.camera.to_world(self.vel));
+        let samples = frame.samples as f32;
+        let weight = Vec3dx16::from_tuple((weight.0 / samples, weight.1 / samples, weight.2 / samples));
+
+        for s in 0..frame.samples as u32 {
+            let stream = |k: u32| random(seeds + (8 * s + k));
+
+            // Only jitter within the pixel when there are several samples to
+            // average, otherwise edges would flicker
+            let (jx, jy) = if frame.samples > 1 {
+                (stream(0), stream(1))
+            } else {
+                (f32x16::splat(0.5), f32x16::splat(0.5))
+            };
+
+            let us = (pxs + jx) / width as f32 * 2. - 1.;
+            let vs = 1. - (pys + jy) / height as f32 * 2.;
+
+            let (origins, dirs, covered) = gen(us, vs, (stream(2), stream(3)));
+
+            // The camera was further back along its path earlier in the
+            // exposure. A single sample is taken in the middle of it.
+            let before = if frame.samples > 1 {
+                stream(4) * -self.shutter
+            } else {
+                f32x16::splat(-self.shutter / 2.)
+            };
+
+            let origins = origins + vel * Vec3dx16::splat(before);
+            queue.rays.push(origins, dirs, packet.starts, before + frame.time);
+            queue.weights.push(Vec3dx16::select(covered, &weight, &Vec3dx16::default()));
+            queue.pixels.push(packet.index);
+        }
+    }
+
+    // How far along the rays in each CONE_TILE square of the tile marching
+    // can start. A cone around all the rays of a square is marched until it
+    // gets close to a surface. Zero where cones can't be used, when the rays
+    // don't all come from one point, or when the world moves during the
+    // shutter so the rays see it somewhere else than the cones do.
+    fn cone_starts(&self, frame: &Frame, tile: &Tile) -> f32x16 {
+        let (camera, settings) = (&frame.camera, &frame.settings);
+        let (width, height) = (frame.width, frame.height);
+        let zeros = f32x16::splat(0.);
+
+        let usable = self.prepass
+            && self.stereo.mode == StereoMode::Mono
+            && camera.projection == Projection::Perspective
+            && camera.aperture == 0.
+            && !(self.shutter > 0. && self.world.animated());
+        if !usable {
+            return zeros;
+        }
+
+        // Corners of the squares, cut off by the edges of the tile
+        let mut x0s = zeros;
+        let mut y0s = zeros;
+        let mut x1s = zeros;
+        let mut y1s = zeros;
+        for i in 0..16 {
+            let x0 = (i % 4 * CONE_TILE).min(tile.width - 1);
+            let y0 = (i / 4 * CONE_TILE).min(tile.height - 1);
+            x0s = x0s.replace(i, (tile.x + x0) as f32);
+            y0s = y0s.replace(i, (tile.y + y0) as f32);
+            x1s = x1s.replace(i, (tile.x + (x0 + CONE_TILE).min(tile.width)) as f32);
+            y1s = y1s.replace(i, (tile.y + (y0 + CONE_TILE).min(tile.height)) as f32);
+        }
+
+        let dir = |pxs: f32x16, pys: f32x16| {
+            let us = pxs / width as f32 * 2. - 1.;
+            let vs = 1. - pys / height as f32 * 2.;
+            let (_, dirs) = camera.rays(us, vs, (zeros, zeros));
+            dirs / Vec3dx16::splat(norm(&dirs))
+        };
+
+        let axes = dir((x0s + x1s) / 2., (y0s + y1s) / 2.);
+
+        // The cones' slopes, from the widest angle between the axis and a
+        // corner
+        let mut cos = f32x16::splat(1.);
+        for &(pxs, pys) in &[(x0s, y0s), (x1s, y0s), (x0s, y1s), (x1s, y1s)] {
+            cos = cos.min(axes.dot(&dir(pxs, pys)));
+        }
+        let slope = (1. - cos * cos).sqrt() / cos;
+
+        // Motion blur moves the camera this far back along its path
+        let vel = camera.to_world(self.vel);
+        let margin = (vel.0 * vel.0 + vel.1 * vel.1 + vel.2 * vel.2).sqrt() * self.shutter;
+
+        let origins = Vec3dx16::from_tuple(camera.position);
+        let mut depths = zeros;
+        let mut active = m32x16::splat(true);
+
+        for _ in 0..settings.iterations {
+            let poses = origins + axes * Vec3dx16::splat(depths + camera.near);
+            let des = self.world.distance_estimator(&poses, f32x16::splat(frame.time));
+
+            // Every ray of the square is within this distance of the axis
+            let radius = (depths + camera.near) * slope + margin;
+            // The cone widens by `slope` for every unit it goes forward, so
+            // steps longer than this could take it past surfaces
+            let step = (des - radius) / (1. + slope) * settings.step_scale.min(1.);
+
+            active &= step.gt(f32x16::splat(settings.epsilon))
+                & depths.lt(f32x16::splat(settings.max_distance));
+            if active.none() {
+                break;
+            }
+
+            depths += active.select(step, zeros);
+        }
+
+        depths
+    }
+
+    // Colors of all the queued rays
+    fn march(&self, frame: &Frame, queue: &Queue) -> Vec<Vec3dx16> {
+        let (world, rays, settings) = (&*self.world, &queue.rays, &frame.settings);
+
+        if self.compact {
+            raymarch_packets(world, rays, settings)
+        } else {
+            (0..rays.len())
+                .map(|k| {
+                    let mut packet = Rays::default();
+                    packet.push(rays.origins[k], rays.dirs[k], rays.starts[k], rays.times[k]);
+                    raymarch_packets(world, &packet, settings)[0]
+                })
+                .collect()
+        }
+    }
+
+    // Renders the current view into any framebuffer, whatever its size
+    pub fn render_into(&self, framebuffer: &mut Framebuffer) {
+        let frame = self.frame(framebuffer.width(), framebuffer.height());
+
+        // Tiles are small enough that rayon can balance expensive regions of
+        // the image across all threads
+        let (packet_width, packet_height) = self.layout.size();
+
+        framebuffer.tiles(TILE).into_par_iter().for_each(|mut tile| {
+            // Every ray in the tile is queued before marching, so that lanes
+            // can be refilled from the whole tile
+            let mut packets = Vec::new();
+            let mut queue = Queue::default();
+
+            let cones = self.cone_starts(&frame, &tile);
+
+            for y in (0..tile.height).step_by(packet_height) {
+                for x in (0..tile.width).step_by(packet_width) {
+                    // Packets along the right and bottom edges may be partial.
+                    // Lanes outside the tile repeat the nearest pixel inside
+                    // and are never written
+                    let last_x = (tile.width - x).min(packet_width) - 1;
+                    let last_y = (tile.height - y).min(packet_height) - 1;
+
+                    let zeros = f32x16::splat(0.);
+                    let mut packet = Packet {
+                        pxs: zeros,
+                        pys: zeros,
+                        starts: zeros,
+                        index: packets.len(),
+                    };
+                    for i in 0..16 {
+                        let dx = (i % packet_width).min(last_x);
+                        let dy = (i / packet_width).min(last_y);
+                        packet.pxs = packet.pxs.replace(i, (tile.x + x + dx) as f32);
+                        packet.pys = packet.pys.replace(i, (tile.y + y + dy) as f32);
+
+                        let cone = (y + dy) / CONE_TILE * 4 + (x + dx) / CONE_TILE;
+                        packet.starts = packet.starts.replace(i, cones.extract(cone));
                     }
+
+                    self.shade(&frame, &packet, &mut queue);
+                    packets.push((x, y, last_x, last_y));
                 }
+            }
+
+            let mut res_cols = vec![Vec3dx16::default(); packets.len()];
+            for (k, colors) in self.march(&frame, &queue).into_iter().enumerate() {
+                res_cols[queue.pixels[k]] += colors * queue.weights[k];
+            }
+
+            for (&(x, y, last_x, last_y), cols) in packets.iter().zip(&res_cols) {
+                let re = u8x16::from_cast(255. * cols.xs);
+                let gr = u8x16::from_cast(255. * cols.ys);
+                let bl = u8x16::from_cast(255. * cols.zs);
 
-                dones.fetch_add(1, Ordering::SeqCst);
+                for i in 0..16 {
+                    let (dx, dy) = (i % packet_width, i / packet_width);
+                    if dx <= last_x && dy <= last_y {
+                        tile.set(x + dx, y + dy, (re.extract(i), gr.extract(i), bl.extract(i)));
+                    }
+                }
             }
         });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::march::march;
+
+    // Marching from where the cones got to skips no surface that marching
+    // the whole ray would hit
+    #[test]
+    fn cones_stop_before_hits() {
+        // Few pixels, so the cones are wide
+        let (width, height) = (24, 16);
+
+        for name in scenes::Scene::names() {
+            let scene = scenes::Scene::from_name(name).unwrap();
+            let mut renderer = Renderer::new(width, height);
+            renderer.set_scene(SceneSettings::new(scene));
+            renderer.march_settings.iterations = 100;
+            let frame = renderer.frame(width, height);
+
+            // From the camera, with enough steps to reach every surface
+            let settings = MarchSettings { iterations: 1000, ..frame.settings };
+
+            let mut data = vec![0; 4 * width * height];
+            let mut framebuffer = Framebuffer::new(&mut data, width, height);
+            for tile in framebuffer.tiles(TILE) {
+                let cones = renderer.cone_starts(&frame, &tile);
+
+                let mut rays = Rays::default();
+                let mut starts = Vec::new();
+                for y in 0..tile.height {
+                    for x in 0..tile.width {
+                        let (px, py) = ((tile.x + x) as f32 + 0.5, (tile.y + y) as f32 + 0.5);
+                        let us = f32x16::splat(px / width as f32 * 2. - 1.);
+                        let vs = f32x16::splat(1. - py / height as f32 * 2.);
+                        let zeros = f32x16::splat(0.);
+                        let (origins, dirs) = frame.camera.rays(us, vs, (zeros, zeros));
+                        rays.push(origins, dirs, zeros, f32x16::splat(frame.time));
+                        starts.push(cones.extract(y / CONE_TILE * 4 + x / CONE_TILE));
+                    }
+                }
 
-        // Wait for each thread
-        while dones.load(Ordering::SeqCst) != THREADS {
-            sleep(Duration::from_millis(1));
+                for (marched, &start) in march(&*renderer.world, &rays, &settings).iter().zip(&starts) {
+                    if marched.hit.extract(0) {
+                        let depth = marched.depths.extract(0);
+                        assert!(start <= depth, "{}: cone got to {} past a hit at {}", name, start, depth);
+                    }
+                }
+            }
         }
     }
 }
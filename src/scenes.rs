@@ -0,0 +1,308 @@
+use crate::fractal::*;
+use crate::math::Quat;
+use crate::noise::Field;
+use crate::simd::f32x16;
+use crate::vec::Vec3dx16;
+use crate::world::*;
+
+// The worlds that can be picked with --scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scene {
+    // A cube and a sphere between two planes
+    Default,
+    // `detail` spheres on a lattice
+    Spheres,
+    Mandelbulb,
+    Mandelbox,
+    Julia,
+    Menger,
+    Sierpinski,
+    Kifs,
+    // A row of shapes, each changed by one of the domain operators and
+    // modifiers
+    Modifiers,
+    // Shapes moving over a floor, blurred by the shutter
+    Motion,
+}
+
+const SCENES: [Scene; 10] = [
+    Scene::Default,
+    Scene::Spheres,
+    Scene::Mandelbulb,
+    Scene::Mandelbox,
+    Scene::Julia,
+    Scene::Menger,
+    Scene::Sierpinski,
+    Scene::Kifs,
+    Scene::Modifiers,
+    Scene::Motion,
+];
+
+impl Scene {
+    pub fn from_name(name: &str) -> Option<Scene> {
+        SCENES.iter().copied().find(|scene| scene.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scene::Default => "default",
+            Scene::Spheres => "spheres",
+            Scene::Mandelbulb => "mandelbulb",
+            Scene::Mandelbox => "mandelbox",
+            Scene::Julia => "julia",
+            Scene::Menger => "menger",
+            Scene::Sierpinski => "sierpinski",
+            Scene::Kifs => "kifs",
+            Scene::Modifiers => "modifiers",
+            Scene::Motion => "motion",
+        }
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        SCENES.iter().map(|scene| scene.name()).collect()
+    }
+
+    // The `detail` and `shape` a scene starts with
+    fn defaults(self) -> (usize, f32) {
+        match self {
+            Scene::Default => (0, 0.),
+            Scene::Spheres => (1000, 0.),
+            Scene::Mandelbulb => (8, 8.),
+            Scene::Mandelbox => (12, -1.5),
+            Scene::Julia => (11, 0.),
+            Scene::Menger => (5, 3.),
+            Scene::Sierpinski => (10, 2.),
+            Scene::Kifs => (8, 0.3),
+            Scene::Modifiers => (0, 1.),
+            Scene::Motion => (0, 1.),
+        }
+    }
+
+    // How much one key press changes `shape`, and the range it stays in
+    fn shape_steps(self) -> (f32, f32, f32) {
+        match self {
+            Scene::Default | Scene::Spheres => (0., 0., 0.),
+            Scene::Modifiers => (0.25, 0., 2.),
+            Scene::Motion => (0.25, 0., 4.),
+            Scene::Mandelbulb => (1., 2., 16.),
+            Scene::Mandelbox => (0.25, -3., 3.),
+            Scene::Julia => (0.1, -1., 1.),
+            Scene::Menger => (0.25, 1.5, 4.),
+            Scene::Sierpinski => (0.1, 1.5, 3.),
+            Scene::Kifs => (0.05, -1., 1.),
+        }
+    }
+}
+
+// A scene with the parameters it's built with, changed by the keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneSettings {
+    pub scene: Scene,
+    // Fractal iterations, or the number of spheres
+    pub detail: usize,
+    // The main parameter of a fractal: the Mandelbulb power, the scale of
+    // the Mandelbox, sponge and tetrahedron, the 4th coordinate of the Julia
+    // slice and the rotation of the KIFS in radians. For the modifiers, how
+    // strong they are, and for the moving shapes how fast they go.
+    pub shape: f32,
+}
+
+impl SceneSettings {
+    pub fn new(scene: Scene) -> SceneSettings {
+        let (detail, shape) = scene.defaults();
+        SceneSettings { scene, detail, shape }
+    }
+
+    pub fn next_scene(&mut self) {
+        let i = SCENES.iter().position(|&scene| scene == self.scene).unwrap_or(0);
+        *self = SceneSettings::new(SCENES[(i + 1) % SCENES.len()]);
+    }
+
+    pub fn change_detail(&mut self, more: bool) {
+        self.detail = match (self.scene, more) {
+            (Scene::Default, _) | (Scene::Modifiers, _) | (Scene::Motion, _) => 0,
+            (Scene::Spheres, true) => self.detail * 2,
+            (Scene::Spheres, false) => (self.detail / 2).max(1),
+            (_, true) => self.detail + 1,
+            (_, false) => self.detail.max(2) - 1,
+        };
+    }
+
+    pub fn change_shape(&mut self, more: bool) {
+        let (step, min, max) = self.scene.shape_steps();
+        let shape = if more { self.shape + step } else { self.shape - step };
+        self.shape = shape.max(min).min(max);
+    }
+
+    pub fn build(&self) -> Box<dyn World> {
+        let (detail, shape) = (self.detail, self.shape);
+        // Fractals sit in front of the camera, which starts at the origin
+        let at = (0., 0., 3.);
+
+        match self.scene {
+            Scene::Default => Box::new(default_scene()),
+            Scene::Modifiers => Box::new(modifiers_scene(shape)),
+            Scene::Motion => Box::new(motion_scene(shape)),
+            Scene::Spheres => Box::new(Union::new(construct_sphere_field(detail))),
+            Scene::Mandelbulb => {
+                let bulb = Mandelbulb { power: shape, iterations: detail, ..Mandelbulb::default() };
+                let palette = Palette::Cosine {
+                    a: (0.5, 0.5, 0.5),
+                    b: (0.5, 0.5, 0.5),
+                    c: (0.15, 0.15, 0.15),
+                    d: (0.8, 0.9, 0.3),
+                };
+                Box::new(Translation::new(Trapped::new(bulb, Trap::SmoothIterations, palette), at))
+            }
+            Scene::Mandelbox => {
+                let mandelbox = Mandelbox { scale: shape, iterations: detail, ..Mandelbox::default() };
+                let palette = Palette::Gradient(vec![
+                    (0., (1., 0.9, 0.6)),
+                    (0.5, (0.8, 0.3, 0.1)),
+                    (2., (0.1, 0.1, 0.3)),
+                ]);
+                let trapped = Trapped::new(mandelbox, Trap::Plane((0., 1., 0.), 0.), palette);
+                // Bring it down to the size of the other fractals
+                Box::new(Translation::new(Scale::new(trapped, (0.4, 0.4, 0.4)), (0., 0., 4.)))
+            }
+            Scene::Julia => {
+                let julia = QuaternionJulia { slice: shape, iterations: detail, ..QuaternionJulia::default() };
+                let palette = Palette::Cosine {
+                    a: (0.5, 0.5, 0.5),
+                    b: (0.5, 0.5, 0.5),
+                    c: (0.1, 0.1, 0.1),
+                    d: (0., 0.33, 0.67),
+                };
+                Box::new(Translation::new(Trapped::new(julia, Trap::Iterations, palette), at))
+            }
+            Scene::Menger => {
+                // Colored by the default trap
+                let sponge = MengerSponge { scale: shape, iterations: detail, ..MengerSponge::default() };
+                let sponge = Rotation::new(Rotation::new(sponge, Axis::Y, 0.6), Axis::X, 0.4);
+                Box::new(Translation::new(sponge, at))
+            }
+            Scene::Sierpinski => {
+                let tetrahedron = SierpinskiTetrahedron {
+                    scale: shape,
+                    iterations: detail,
+                    ..SierpinskiTetrahedron::default()
+                };
+                let palette = Palette::Gradient(vec![(0., (1., 1., 1.)), (1., (0.2, 0.5, 1.))]);
+                let trapped = Trapped::new(tetrahedron, Trap::Axis((1., 1., 1.)), palette);
+                Box::new(Translation::new(trapped, at))
+            }
+            Scene::Kifs => {
+                let ball = Coloring::new(UnitSphere, (0.9, 0.6, 0.3));
+                let kifs = Kifs::new(ball, 2., (1., 1., 1.), detail)
+                    .fold((1., 1., 0.))
+                    .fold((1., 0., 1.))
+                    .fold((0., 1., 1.))
+                    .rotate(Quat::from_axis_angle((1., 1., 0.), shape));
+                Box::new(Translation::new(Scale::new(kifs, (0.5, 0.5, 0.5)), at))
+            }
+        }
+    }
+}
+
+fn default_scene() -> Union {
+    let cube = construct_cuboid((1., -2., 5.), (0.5, 0.5, 0.5));
+    let cube = Coloring::new(cube, (0., 1., 0.));
+
+    let sphere = construct_sphere((-4., 0., 7.), 1.);
+    let sphere = Checkers::new(sphere, (0., 0., 0.), (1., 1., 0.));
+
+    // let cubesphere = Box::new(Intersection {
+    //     objects: vec![cube, sphere],
+    // });
+
+    let ground = Plane { height: -10. };
+    let ground = Checkers::new(ground, (0., 0., 0.), (1., 1., 0.));
+
+    let roof = Plane { height: 10. };
+    let roof = Coloring::new(roof, (0.57, 0.31, 0.07));
+
+    Union::new(vec![Box::new(cube), Box::new(sphere), Box::new(ground), Box::new(roof)])
+}
+
+// Colors a shape and moves it into place
+fn place<W: World + 'static>(shape: W, color: (f32, f32, f32), at: (f32, f32, f32)) -> Box<dyn World> {
+    Box::new(Translation::new(Coloring::new(shape, color), at))
+}
+
+// Deformations divide their distance by how much they stretch space, which
+// grows far away from them, so rays passing by take tiny steps. Out there
+// the distance to their bounds is a better estimate.
+fn bounded<W: World + 'static>(shape: W) -> Intersection {
+    let bounds = shape.bounds().expect("Only bounded shapes can be bounded");
+    let (min, max) = (bounds.min, bounds.max);
+    let half = ((max.0 - min.0) / 2., (max.1 - min.1) / 2., (max.2 - min.2) / 2.);
+    Intersection {
+        objects: vec![Box::new(shape), Box::new(construct_cuboid(bounds.center(), half))],
+    }
+}
+
+// Two rows of five shapes, `strength` scales how much each is changed
+fn modifiers_scene(strength: f32) -> Union {
+    let k = strength;
+    let ball = || Scale::new(UnitSphere, (0.6, 0.6, 0.6));
+    let cube = || Scale::new(UnitCube, (0.5, 0.5, 0.5));
+    let at = |i: usize| ((i % 5) as f32 * 3. - 6., if i < 5 { 1.6 } else { -1.6 }, 14.);
+
+    let pair = Translation::new(Scale::new(UnitSphere, (0.4, 0.4, 0.4)), (0.5 * k, 0., 0.));
+    let ring = Translation::new(Scale::new(UnitCube, (0.2, 0.2, 0.2)), (0., 0., 0.3 + 0.5 * k));
+    // Cut open to show the layers
+    let layers = Intersection {
+        objects: vec![
+            Box::new(Onion::nested(ball(), 0.1 + 0.1 * k, 2)),
+            Box::new(Translation::new(UnitCube, (0., 0., 1.))),
+        ],
+    };
+    let turned = Quat::from_axis_angle((1., 1., 0.), k);
+
+    Union::new(vec![
+        place(Mirror::axes(pair, &[Axis::X]), (1., 0.3, 0.8), at(0)),
+        place(Kaleidoscope::new(ring, Axis::Y, 6), (1., 0.6, 0.2), at(1)),
+        place(bounded(Twist::new(Scale::new(UnitCube, (0.4, 1., 0.4)), Axis::Y, 1.5 * k)), (1., 1., 0.3), at(2)),
+        place(bounded(Bend::new(Scale::new(UnitCube, (1., 0.2, 0.3)), Axis::Z, 0.6 * k)), (0.4, 1., 0.4), at(3)),
+        place(bounded(Taper::new(Scale::new(UnitCube, (0.5, 1., 0.5)), Axis::Y, 0.6 * k)), (0.3, 1., 1.), at(4)),
+        place(Displace::new(ball(), Field::Perlin, 0.15 * k, 4.), (0.3, 0.5, 1.), at(5)),
+        place(Round::new(cube(), 0.3 * k), (0.7, 0.4, 1.), at(6)),
+        place(layers, (1., 1., 1.), at(7)),
+        place(Elongate::new(ball(), (0.5 * k, 0., 0.)), (1., 0.5, 0.5), at(8)),
+        place(Transform::from_parts(cube(), turned, (0., 0., 0.), 1.), (0.5, 1., 0.7), at(9)),
+        Box::new(Checkers::new(Plane { height: -4. }, (0.2, 0.2, 0.2), (0.8, 0.8, 0.8))),
+    ])
+}
+
+// A ball swinging from side to side, a cube bobbing up and down and a ball
+// going round them, `speed` times as fast as normal
+fn motion_scene(speed: f32) -> Union {
+    let swing = Animate::new(
+        construct_sphere((0., 0., 8.), 0.8),
+        Box::new(move |t: f32x16| {
+            let zeros = f32x16::splat(0.);
+            Vec3dx16 { xs: (t * (2. * speed)).sin() * 3., ys: zeros, zs: zeros }
+        }),
+    );
+    let bob = Animate::new(
+        construct_cuboid((0., 0., 10.), (0.6, 0.6, 0.6)),
+        Box::new(move |t: f32x16| {
+            let zeros = f32x16::splat(0.);
+            Vec3dx16 { xs: zeros, ys: (t * (3. * speed)).sin() * 1.5, zs: zeros }
+        }),
+    );
+    let orbit = Animate::new(
+        construct_sphere((0., -1., 10.), 0.5),
+        Box::new(move |t: f32x16| {
+            let angle = t * speed;
+            Vec3dx16 { xs: angle.cos() * 4., ys: f32x16::splat(0.), zs: angle.sin() * 4. }
+        }),
+    );
+
+    Union::new(vec![
+        Box::new(Coloring::new(swing, (1., 0.3, 0.2))),
+        Box::new(Coloring::new(bob, (0.3, 1., 0.4))),
+        Box::new(Checkers::new(orbit, (0.2, 0.2, 1.), (1., 1., 1.))),
+        Box::new(Checkers::new(Plane { height: -2. }, (0.2, 0.2, 0.2), (0.8, 0.8, 0.8))),
+    ])
+}
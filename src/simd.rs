@@ -0,0 +1,304 @@
+// 16 lane vectors on top of std::simd, with the method names packed_simd
+// had and arithmetic between a vector and a scalar, which splats the scalar
+#![allow(non_camel_case_types)]
+
+use std::ops::*;
+use std::simd::cmp::{SimdOrd, SimdPartialEq, SimdPartialOrd};
+use std::simd::num::{SimdFloat, SimdInt, SimdUint};
+use std::simd::{Mask, Simd, StdFloat};
+use std::simd::Select as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct m32x16(Mask<i32, 16>);
+
+impl m32x16 {
+    pub fn splat(value: bool) -> m32x16 {
+        m32x16(Mask::splat(value))
+    }
+
+    pub fn any(self) -> bool {
+        self.0.any()
+    }
+
+    pub fn all(self) -> bool {
+        self.0.all()
+    }
+
+    pub fn none(self) -> bool {
+        !self.0.any()
+    }
+
+    pub fn extract(self, lane: usize) -> bool {
+        self.0.test(lane)
+    }
+
+    pub fn replace(mut self, lane: usize, value: bool) -> m32x16 {
+        self.0.set(lane, value);
+        self
+    }
+
+    // `a` where the mask is set and `b` elsewhere
+    pub fn select<T: Select>(self, a: T, b: T) -> T {
+        T::select(self, a, b)
+    }
+}
+
+impl Not for m32x16 {
+    type Output = m32x16;
+    fn not(self) -> m32x16 {
+        m32x16(!self.0)
+    }
+}
+
+macro_rules! mask_op {
+    ($op:ident, $f:ident, $op_assign:ident, $f_assign:ident) => {
+        impl $op for m32x16 {
+            type Output = m32x16;
+            fn $f(self, other: m32x16) -> m32x16 {
+                m32x16(self.0.$f(other.0))
+            }
+        }
+
+        impl $op_assign for m32x16 {
+            fn $f_assign(&mut self, other: m32x16) {
+                *self = self.$f(other);
+            }
+        }
+    };
+}
+
+mask_op!(BitAnd, bitand, BitAndAssign, bitand_assign);
+mask_op!(BitOr, bitor, BitOrAssign, bitor_assign);
+mask_op!(BitXor, bitxor, BitXorAssign, bitxor_assign);
+
+pub trait Select {
+    fn select(mask: m32x16, a: Self, b: Self) -> Self;
+}
+
+pub trait FromCast<T> {
+    fn from_cast(from: T) -> Self;
+}
+
+macro_rules! vector {
+    ($name:ident, $elem:ty) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Default)]
+        pub struct $name(Simd<$elem, 16>);
+
+        impl $name {
+            pub fn splat(value: $elem) -> $name {
+                $name(Simd::splat(value))
+            }
+
+            pub fn extract(self, lane: usize) -> $elem {
+                self.0[lane]
+            }
+
+            pub fn replace(mut self, lane: usize, value: $elem) -> $name {
+                self.0[lane] = value;
+                self
+            }
+
+            pub fn min(self, other: $name) -> $name {
+                $name(self.0.simd_min(other.0))
+            }
+
+            pub fn max(self, other: $name) -> $name {
+                $name(self.0.simd_max(other.0))
+            }
+
+            pub fn sum(self) -> $elem {
+                self.0.reduce_sum()
+            }
+
+            pub fn eq(self, other: $name) -> m32x16 {
+                m32x16(self.0.simd_eq(other.0).cast())
+            }
+
+            pub fn ne(self, other: $name) -> m32x16 {
+                m32x16(self.0.simd_ne(other.0).cast())
+            }
+
+            pub fn lt(self, other: $name) -> m32x16 {
+                m32x16(self.0.simd_lt(other.0).cast())
+            }
+
+            pub fn le(self, other: $name) -> m32x16 {
+                m32x16(self.0.simd_le(other.0).cast())
+            }
+
+            pub fn gt(self, other: $name) -> m32x16 {
+                m32x16(self.0.simd_gt(other.0).cast())
+            }
+
+            pub fn ge(self, other: $name) -> m32x16 {
+                m32x16(self.0.simd_ge(other.0).cast())
+            }
+        }
+
+        vector_op!($name, $elem, Add, add, AddAssign, add_assign);
+        vector_op!($name, $elem, Sub, sub, SubAssign, sub_assign);
+        vector_op!($name, $elem, Mul, mul, MulAssign, mul_assign);
+        vector_op!($name, $elem, Div, div, DivAssign, div_assign);
+        vector_op!($name, $elem, Rem, rem, RemAssign, rem_assign);
+    };
+}
+
+macro_rules! vector_op {
+    ($name:ident, $elem:ty, $op:ident, $f:ident, $op_assign:ident, $f_assign:ident) => {
+        impl $op for $name {
+            type Output = $name;
+            fn $f(self, other: $name) -> $name {
+                $name(self.0.$f(other.0))
+            }
+        }
+
+        impl $op<$elem> for $name {
+            type Output = $name;
+            fn $f(self, other: $elem) -> $name {
+                self.$f($name::splat(other))
+            }
+        }
+
+        impl $op<$name> for $elem {
+            type Output = $name;
+            fn $f(self, other: $name) -> $name {
+                $name::splat(self).$f(other)
+            }
+        }
+
+        impl $op_assign for $name {
+            fn $f_assign(&mut self, other: $name) {
+                *self = self.$f(other);
+            }
+        }
+
+        impl $op_assign<$elem> for $name {
+            fn $f_assign(&mut self, other: $elem) {
+                *self = self.$f(other);
+            }
+        }
+    };
+}
+
+macro_rules! bit_op {
+    ($name:ident, $elem:ty, $op:ident, $f:ident) => {
+        impl $op for $name {
+            type Output = $name;
+            fn $f(self, other: $name) -> $name {
+                $name(self.0.$f(other.0))
+            }
+        }
+
+        impl $op<$elem> for $name {
+            type Output = $name;
+            fn $f(self, other: $elem) -> $name {
+                self.$f($name::splat(other))
+            }
+        }
+    };
+}
+
+macro_rules! select {
+    ($name:ident) => {
+        impl Select for $name {
+            fn select(mask: m32x16, a: $name, b: $name) -> $name {
+                $name(mask.0.select(a.0, b.0))
+            }
+        }
+    };
+}
+
+macro_rules! cast {
+    ($from:ident, $to:ident, $elem:ty) => {
+        impl FromCast<$from> for $to {
+            // Like `as` on each lane
+            fn from_cast(from: $from) -> $to {
+                $to(from.0.cast::<$elem>())
+            }
+        }
+    };
+}
+
+vector!(f32x16, f32);
+vector!(i32x16, i32);
+vector!(u32x16, u32);
+vector!(u8x16, u8);
+
+bit_op!(u32x16, u32, BitAnd, bitand);
+bit_op!(u32x16, u32, BitOr, bitor);
+bit_op!(u32x16, u32, BitXor, bitxor);
+
+select!(f32x16);
+select!(i32x16);
+select!(u32x16);
+
+cast!(f32x16, i32x16, i32);
+cast!(f32x16, u32x16, u32);
+cast!(f32x16, u8x16, u8);
+cast!(i32x16, f32x16, f32);
+cast!(i32x16, u32x16, u32);
+cast!(u32x16, f32x16, f32);
+
+// True lanes are -1, like the bits of a mask read as an integer
+impl FromCast<m32x16> for f32x16 {
+    fn from_cast(from: m32x16) -> f32x16 {
+        f32x16(from.0.to_simd().cast())
+    }
+}
+
+impl Neg for f32x16 {
+    type Output = f32x16;
+    fn neg(self) -> f32x16 {
+        f32x16(-self.0)
+    }
+}
+
+impl Shr<u32> for u32x16 {
+    type Output = u32x16;
+    fn shr(self, by: u32) -> u32x16 {
+        u32x16(self.0 >> Simd::splat(by))
+    }
+}
+
+impl Shl<u32> for u32x16 {
+    type Output = u32x16;
+    fn shl(self, by: u32) -> u32x16 {
+        u32x16(self.0 << Simd::splat(by))
+    }
+}
+
+impl f32x16 {
+    pub fn abs(self) -> f32x16 {
+        f32x16(self.0.abs())
+    }
+
+    pub fn sqrt(self) -> f32x16 {
+        f32x16(self.0.sqrt())
+    }
+
+    pub fn floor(self) -> f32x16 {
+        f32x16(self.0.floor())
+    }
+
+    pub fn sin(self) -> f32x16 {
+        f32x16(self.0.sin())
+    }
+
+    pub fn cos(self) -> f32x16 {
+        f32x16(self.0.cos())
+    }
+
+    pub fn exp(self) -> f32x16 {
+        f32x16(self.0.exp())
+    }
+
+    pub fn ln(self) -> f32x16 {
+        f32x16(self.0.ln())
+    }
+
+    // Lane by lane, std::simd has no vector version
+    pub fn powf(self, other: f32x16) -> f32x16 {
+        let (xs, ps) = (self.0.to_array(), other.0.to_array());
+        f32x16(Simd::from_array(std::array::from_fn(|i| xs[i].powf(ps[i]))))
+    }
+}
@@ -1,4 +1,4 @@
-use packed_simd::f32x16;
+use crate::simd::{f32x16, m32x16};
 
 #[derive(Debug, PartialEq, Default, Clone, Copy)]
 pub struct Vec3dx16 {
@@ -18,11 +18,34 @@ impl Vec3dx16 {
 
     pub fn splat(val: f32x16) -> Vec3dx16 {
         Vec3dx16 {
-            xs: val.clone(),
-            ys: val.clone(),
-            zs: val.clone(),
+            xs: val,
+            ys: val,
+            zs: val,
         }
     }
+
+    // Lanes from a where the mask is set, otherwise from b
+    pub fn select(mask: m32x16, a: &Vec3dx16, b: &Vec3dx16) -> Vec3dx16 {
+        Vec3dx16 {
+            xs: mask.select(a.xs, b.xs),
+            ys: mask.select(a.ys, b.ys),
+            zs: mask.select(a.zs, b.zs),
+        }
+    }
+
+    pub fn dot(&self, other: &Vec3dx16) -> f32x16 {
+        self.xs * other.xs + self.ys * other.ys + self.zs * other.zs
+    }
+
+    pub fn extract(&self, lane: usize) -> (f32, f32, f32) {
+        (self.xs.extract(lane), self.ys.extract(lane), self.zs.extract(lane))
+    }
+
+    pub fn replace(&mut self, lane: usize, (x, y, z): (f32, f32, f32)) {
+        self.xs = self.xs.replace(lane, x);
+        self.ys = self.ys.replace(lane, y);
+        self.zs = self.zs.replace(lane, z);
+    }
 }
 
 macro_rules! impl_op {
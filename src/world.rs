@@ -1,27 +1,180 @@
 use std::borrow::Borrow;
 use std::marker::PhantomData;
 
+use crate::bvh::Bvh;
+use crate::math::{atan2, floor, Mat3x4, Quat};
+use crate::noise::Field;
 use crate::vec::Vec3dx16;
-use packed_simd::{u32x16, f32x16, m32x16, FromCast};
-use std::f32::{INFINITY, NEG_INFINITY};
+use crate::simd::{u32x16, f32x16, FromCast};
+use std::f32::consts::PI;
 
-const EPSILON: f32 = 1e-2;
-const MAX_ITERATIONS: usize = 30;
 
 pub fn norm(v: &Vec3dx16) -> f32x16 {
     (v.xs * v.xs + v.ys * v.ys + v.zs * v.zs).sqrt()
 }
 
+// Every method gets the time of each lane, in seconds, for worlds that move
 pub trait World: Send + Sync {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16;
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16;
 
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16;
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16;
+
+    // The distance together with the color of the closest surface. Worlds
+    // that can share work between the two should override this.
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        (self.distance_estimator(x, time), self.color(x, time))
+    }
+
+    // A box containing every point where the distance is zero, if the
+    // world is finite and its extent is known. Wrappers that offset the
+    // distance, like `Round`, assume the inner distance is exact away from
+    // the surface, an underestimate grows the shape further than that.
+    fn bounds(&self) -> Option<Bounds> {
+        None
+    }
+
+    // Whether the distance depends on the time
+    fn animated(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: (f32, f32, f32),
+    pub max: (f32, f32, f32),
+}
+
+impl Bounds {
+    pub fn new(min: (f32, f32, f32), max: (f32, f32, f32)) -> Bounds {
+        Bounds { min, max }
+    }
+
+    pub fn from_points(points: &[(f32, f32, f32)]) -> Bounds {
+        let mut res = Bounds::new((f32::INFINITY, f32::INFINITY, f32::INFINITY), (f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY));
+        for &p in points {
+            res = res.union(&Bounds::new(p, p));
+        }
+        res
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds::new(
+            (self.min.0.min(other.min.0), self.min.1.min(other.min.1), self.min.2.min(other.min.2)),
+            (self.max.0.max(other.max.0), self.max.1.max(other.max.1), self.max.2.max(other.max.2)),
+        )
+    }
+
+    pub fn intersection(&self, other: &Bounds) -> Bounds {
+        Bounds::new(
+            (self.min.0.max(other.min.0), self.min.1.max(other.min.1), self.min.2.max(other.min.2)),
+            (self.max.0.min(other.max.0), self.max.1.min(other.max.1), self.max.2.min(other.max.2)),
+        )
+    }
+
+    pub fn expand(&self, by: (f32, f32, f32)) -> Bounds {
+        Bounds::new(
+            (self.min.0 - by.0, self.min.1 - by.1, self.min.2 - by.2),
+            (self.max.0 + by.0, self.max.1 + by.1, self.max.2 + by.2),
+        )
+    }
+
+    // False if any side is infinite or NaN
+    pub fn is_finite(&self) -> bool {
+        let (min, max) = (self.min, self.max);
+        [min.0, min.1, min.2, max.0, max.1, max.2].iter().all(|v| v.is_finite())
+    }
+
+    pub fn center(&self) -> (f32, f32, f32) {
+        (
+            (self.min.0 + self.max.0) / 2.,
+            (self.min.1 + self.max.1) / 2.,
+            (self.min.2 + self.max.2) / 2.,
+        )
+    }
+
+    pub fn corners(&self) -> [(f32, f32, f32); 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            (a.0, a.1, a.2), (b.0, a.1, a.2), (a.0, b.1, a.2), (b.0, b.1, a.2),
+            (a.0, a.1, b.2), (b.0, a.1, b.2), (a.0, b.1, b.2), (b.0, b.1, b.2),
+        ]
+    }
+
+    // Maps the box through `f` a packet at a time, and bounds the result
+    fn map_corners(&self, f: impl Fn(&Vec3dx16) -> Vec3dx16) -> Bounds {
+        let mut x = Vec3dx16::from_tuple(self.min);
+        for (i, &corner) in self.corners().iter().enumerate() {
+            x.replace(i, corner);
+        }
+        let x = f(&x);
+        let corners: Vec<_> = (0..8).map(|i| x.extract(i)).collect();
+        Bounds::from_points(&corners)
+    }
+
+    // The box containing every rotation of this one around the axis
+    fn revolve(&self, around: Axis) -> Bounds {
+        let (lo, hi) = (self.min, self.max);
+        // The corner furthest from the axis
+        let radius = |u: (f32, f32), v: (f32, f32)| {
+            let u = u.0.abs().max(u.1.abs());
+            let v = v.0.abs().max(v.1.abs());
+            (u * u + v * v).sqrt()
+        };
+        match around {
+            Axis::X => {
+                let r = radius((lo.1, hi.1), (lo.2, hi.2));
+                Bounds::new((lo.0, -r, -r), (hi.0, r, r))
+            }
+            Axis::Y => {
+                let r = radius((lo.2, hi.2), (lo.0, hi.0));
+                Bounds::new((-r, lo.1, -r), (r, hi.1, r))
+            }
+            Axis::Z => {
+                let r = radius((lo.0, hi.0), (lo.1, hi.1));
+                Bounds::new((-r, -r, lo.2), (r, r, hi.2))
+            }
+        }
+    }
+
+    // Distance from each lane to the box, zero inside it
+    pub fn distance(&self, x: &Vec3dx16) -> f32x16 {
+        let zero = f32x16::splat(0.);
+        let outside = |v: f32x16, min: f32, max: f32| {
+            (f32x16::splat(min) - v).max(v - f32x16::splat(max)).max(zero)
+        };
+        let dx = outside(x.xs, self.min.0, self.max.0);
+        let dy = outside(x.ys, self.min.1, self.max.1);
+        let dz = outside(x.zs, self.min.2, self.max.2);
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Axis {
     X, Y, Z
 }
 
+impl Axis {
+    // Splits x into the component along the axis and the two components
+    // perpendicular to it, ordered so (along, u, v) is right handed
+    fn split(&self, x: &Vec3dx16) -> (f32x16, f32x16, f32x16) {
+        match self {
+            Axis::X => (x.xs, x.ys, x.zs),
+            Axis::Y => (x.ys, x.zs, x.xs),
+            Axis::Z => (x.zs, x.xs, x.ys),
+        }
+    }
+
+    fn join(&self, along: f32x16, u: f32x16, v: f32x16) -> Vec3dx16 {
+        match self {
+            Axis::X => Vec3dx16 { xs: along, ys: u, zs: v },
+            Axis::Y => Vec3dx16 { xs: v, ys: along, zs: u },
+            Axis::Z => Vec3dx16 { xs: u, ys: v, zs: along },
+        }
+    }
+}
+
 pub struct Checkers<T, TBor>
     where
         T: Borrow<TBor> + Send + Sync,
@@ -33,7 +186,9 @@ pub struct Checkers<T, TBor>
     marker: PhantomData<TBor>
 }
 
+#[allow(dead_code)]
 pub type CheckerRef<'a, T> = Checkers<&'a T, T>;
+#[allow(dead_code)]
 pub type CheckerT<T> = Checkers<T, T>;
 
 impl <T, TBor> Checkers<T, TBor>
@@ -53,12 +208,12 @@ impl <T, TBor> World for Checkers<T, TBor>
         T: Borrow<TBor> + Send + Sync,
         TBor: World
 {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
         // haha
-        self.inner.borrow().distance_estimator(x)
+        self.inner.borrow().distance_estimator(x, time)
     }
 
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
+    fn color(&self, x: &Vec3dx16, _time: f32x16) -> Vec3dx16 {
         let xs = u32x16::from_cast(x.xs.abs() + f32x16::splat(0.5));
         let ys = u32x16::from_cast(x.ys.abs() + f32x16::splat(0.5));
         let zs = u32x16::from_cast(x.zs.abs() + f32x16::splat(0.5));
@@ -69,6 +224,14 @@ impl <T, TBor> World for Checkers<T, TBor>
         mask * Vec3dx16::from_tuple(self.color1)
             + (Vec3dx16::from_tuple((1., 1., 1.,)) - mask) * Vec3dx16::from_tuple(self.color2)
     }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.inner.borrow().bounds()
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
 }
 
 pub struct Coloring<T, TBor>
@@ -81,7 +244,9 @@ pub struct Coloring<T, TBor>
     marker: PhantomData<TBor>
 }
 
+#[allow(dead_code)]
 pub type ColorRef<'a, T> = Coloring<&'a T, T>;
+#[allow(dead_code)]
 pub type ColorT<T> = Coloring<T, T>;
 
 impl <T, TBor> Coloring<T, TBor>
@@ -101,14 +266,22 @@ impl <T, TBor> World for Coloring<T, TBor>
         T: Borrow<TBor> + Send + Sync,
         TBor: World
 {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
         // haha
-        self.inner.borrow().distance_estimator(x)
+        self.inner.borrow().distance_estimator(x, time)
     }
 
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
+    fn color(&self, _x: &Vec3dx16, _time: f32x16) -> Vec3dx16 {
         Vec3dx16::from_tuple(self.color)
     }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.inner.borrow().bounds()
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
 }
 
 pub struct Rotation<T, TBor>
@@ -122,7 +295,9 @@ pub struct Rotation<T, TBor>
     marker: PhantomData<TBor>
 }
 
+#[allow(dead_code)]
 pub type RotRef<'a, T> = Rotation<&'a T, T>;
+#[allow(dead_code)]
 pub type RotT<T> = Rotation<T, T>;
 
 impl <T, TBor> Rotation<T, TBor>
@@ -137,9 +312,13 @@ impl <T, TBor> Rotation<T, TBor>
     }
 
     fn transform(&self, x: &Vec3dx16) -> Vec3dx16 {
-        let acos = self.angle.cos();
-        let asin = self.angle.sin();
-        let mut x_ = x.clone();
+        self.rotate(x, self.angle)
+    }
+
+    fn rotate(&self, x: &Vec3dx16, angle: f32) -> Vec3dx16 {
+        let acos = angle.cos();
+        let asin = angle.sin();
+        let mut x_ = *x;
 
         // TODO: Make sure positive direction is consistent here
         match self.around {
@@ -165,12 +344,25 @@ impl <T, TBor> World for Rotation<T, TBor>
         T: Borrow<TBor> + Send + Sync,
         TBor: World
 {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
-        self.inner.borrow().distance_estimator(&self.transform(x))
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x), time)
+    }
+
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        self.inner.borrow().distance_color(&self.transform(x), time)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        // transform goes from this world to the inner one, so turn back
+        let inner = self.inner.borrow().bounds()?;
+        Some(inner.map_corners(|x| self.rotate(x, -self.angle)))
     }
 
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
-        self.inner.borrow().color(&self.transform(x))
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
     }
 }
 
@@ -184,6 +376,7 @@ pub struct Translation<T, TBor>
     marker: PhantomData<TBor>
 }
 
+#[allow(dead_code)]
 pub type TransRef<'a, T> = Translation<&'a T, T>;
 pub type TransT<T> = Translation<T, T>;
 
@@ -199,14 +392,7 @@ impl <T, TBor> Translation<T, TBor>
     }
 
     fn transform(&self, x: &Vec3dx16) -> Vec3dx16 {
-        // haha
-        let splAT = Vec3dx16 {
-            xs: f32x16::splat(self.at.0),
-            ys: f32x16::splat(self.at.1),
-            zs: f32x16::splat(self.at.2),
-        };
-
-        x - splAT
+        x - Vec3dx16::from_tuple(self.at)
     }
 }
 
@@ -215,11 +401,26 @@ impl <T, TBor> World for Translation<T, TBor>
         T: Borrow<TBor> + Send + Sync,
         TBor: World
 {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
-        self.inner.borrow().distance_estimator(&self.transform(x))
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x), time)
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        self.inner.borrow().distance_color(&self.transform(x), time)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let inner = self.inner.borrow().bounds()?;
+        Some(Bounds::new(
+            (inner.min.0 + self.at.0, inner.min.1 + self.at.1, inner.min.2 + self.at.2),
+            (inner.max.0 + self.at.0, inner.max.1 + self.at.1, inner.max.2 + self.at.2),
+        ))
     }
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
-        self.inner.borrow().color(&self.transform(x))
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
     }
 }
 
@@ -233,6 +434,7 @@ pub struct Scale<T, TBor>
     marker: PhantomData<TBor>
 }
 
+#[allow(dead_code)]
 pub type ScaleRef<'a, T> = Scale<&'a T, T>;
 pub type ScaleT<T> = Scale<T, T>;
 
@@ -263,180 +465,1172 @@ impl <T, TBor> World for Scale<T, TBor>
         T: Borrow<TBor> + Send + Sync,
         TBor: World
 {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
-        self.inner.borrow().distance_estimator(&self.transform(x))
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x), time)
             * self.scaling.0.min(self.scaling.1).min(self.scaling.2)
     }
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
-        self.inner.borrow().color(&self.transform(x))
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (distance, color) = self.inner.borrow().distance_color(&self.transform(x), time);
+        (distance * self.scaling.0.min(self.scaling.1).min(self.scaling.2), color)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let inner = self.inner.borrow().bounds()?;
+        let s = self.scaling;
+        let corners: Vec<_> = inner.corners().iter()
+            .map(|c| (c.0 * s.0, c.1 * s.1, c.2 * s.2))
+            .collect();
+        Some(Bounds::from_points(&corners))
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
     }
 }
 
-pub struct Union {
-    pub objects: Vec<Box<dyn World>>
+pub struct Transform<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    matrix: Mat3x4,
+    inverse: Mat3x4,
+    // How much the inverse can stretch distances, distances are divided by this
+    stretch: f32,
+    marker: PhantomData<TBor>
 }
 
-impl World for Union {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
-        let mut res = f32x16::splat(INFINITY);
-        for obj in &self.objects {
-            res = res.min(obj.distance_estimator(x));
+#[allow(dead_code)]
+pub type TransformRef<'a, T> = Transform<&'a T, T>;
+#[allow(dead_code)]
+pub type TransformT<T> = Transform<T, T>;
+
+impl <T, TBor> Transform<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    // The matrix maps points in the inner world to points in this world
+    pub fn new(inner: T, matrix: Mat3x4) -> Transform<T, TBor> {
+        let inverse = matrix.inverse();
+        Transform {
+            inner, matrix, inverse, stretch: inverse.max_stretch(), marker: PhantomData
         }
-        res
     }
 
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
-        let mut distances = f32x16::splat(INFINITY);
-        let mut colors = Vec3dx16::from_tuple((0., 1., 0.));
+    pub fn from_parts(
+        inner: T,
+        rotation: Quat,
+        translation: (f32, f32, f32),
+        scale: f32,
+    ) -> Transform<T, TBor> {
+        Transform::new(inner, Mat3x4::from_parts(rotation, translation, scale))
+    }
 
-        for obj in &self.objects {
-            let distances_ = obj.distance_estimator(x);
-            let closer = distances_.lt(distances);
+    #[allow(dead_code)]
+    pub fn matrix(&self) -> &Mat3x4 {
+        &self.matrix
+    }
 
-            if closer.any() {
-                let mask = -f32x16::from_cast(closer);
+    #[allow(dead_code)]
+    pub fn set_matrix(&mut self, matrix: Mat3x4) {
+        self.matrix = matrix;
+        self.inverse = matrix.inverse();
+        self.stretch = self.inverse.max_stretch();
+    }
+}
 
-                let colors_ = obj.color(x);
-                colors.xs = mask * colors_.xs + (1. - mask) * colors.xs;
-                colors.ys = mask * colors_.ys + (1. - mask) * colors.ys;
-                colors.zs = mask * colors_.zs + (1. - mask) * colors.zs;
-            }
+impl <T, TBor> World for Transform<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.inverse.apply(x), time) / self.stretch
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.inverse.apply(x), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (distance, color) = self.inner.borrow().distance_color(&self.inverse.apply(x), time);
+        (distance / self.stretch, color)
+    }
 
-            distances = distances.min(distances_);
-        }
-        colors
+    fn bounds(&self) -> Option<Bounds> {
+        let inner = self.inner.borrow().bounds()?;
+        let corners: Vec<_> = inner.corners().iter().map(|&c| self.matrix.apply_point(c)).collect();
+        Some(Bounds::from_points(&corners))
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
     }
 }
 
-pub struct Intersection {
-    pub objects: Vec<Box<dyn World>>
+pub struct Twist<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    pub around: Axis,
+    // Radians of rotation per unit along the axis
+    pub rate: f32,
+    marker: PhantomData<TBor>
 }
 
-impl World for Intersection {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
-        let mut res = f32x16::splat(NEG_INFINITY);
-        for obj in &self.objects {
-            res = res.max(obj.distance_estimator(x));
+#[allow(dead_code)]
+pub type TwistRef<'a, T> = Twist<&'a T, T>;
+#[allow(dead_code)]
+pub type TwistT<T> = Twist<T, T>;
+
+impl <T, TBor> Twist<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub fn new(inner: T, around: Axis, rate: f32) -> Twist<T, TBor> {
+        Twist {
+            inner, around, rate, marker: PhantomData
         }
-        res
     }
 
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
-        self.objects[0].color(x)
+    fn transform(&self, x: &Vec3dx16) -> Vec3dx16 {
+        let (along, u, v) = self.around.split(x);
+        let angle = along * self.rate;
+        let (acos, asin) = (angle.cos(), angle.sin());
+
+        self.around.join(along, u * acos - v * asin, u * asin + v * acos)
+    }
+
+    // The twist stretches space more the further out from the axis we are.
+    // This is the largest singular value of its Jacobian, which shears by
+    // `stretch` along the circle around the axis.
+    fn lipschitz(&self, x: &Vec3dx16) -> f32x16 {
+        let (_, u, v) = self.around.split(x);
+        let stretch = (u * u + v * v).sqrt() * self.rate.abs();
+        (stretch + (stretch * stretch + 4.).sqrt()) / 2.
     }
 }
 
+impl <T, TBor> World for Twist<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x), time) / self.lipschitz(x)
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (distance, color) = self.inner.borrow().distance_color(&self.transform(x), time);
+        (distance / self.lipschitz(x), color)
+    }
 
-pub struct UnitSphere;
-
-impl World for UnitSphere {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
-        norm(x) - f32x16::splat(1.)
+    fn bounds(&self) -> Option<Bounds> {
+        // Twisting keeps the distance from the axis
+        Some(self.inner.borrow().bounds()?.revolve(self.around))
     }
 
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
-        Vec3dx16::from_tuple((1., 1., 1.,))
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
     }
 }
 
-pub struct UnitCube;
+pub struct Bend<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    // Bends in the plane perpendicular to this axis
+    pub around: Axis,
+    pub rate: f32,
+    marker: PhantomData<TBor>
+}
 
-impl World for UnitCube {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
-        let xs = x.xs.abs() - f32x16::splat(1.);
-        let ys = x.ys.abs() - f32x16::splat(1.);
-        let zs = x.zs.abs() - f32x16::splat(1.);
-        xs.max(ys).max(zs)
+#[allow(dead_code)]
+pub type BendRef<'a, T> = Bend<&'a T, T>;
+#[allow(dead_code)]
+pub type BendT<T> = Bend<T, T>;
+
+impl <T, TBor> Bend<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub fn new(inner: T, around: Axis, rate: f32) -> Bend<T, TBor> {
+        Bend {
+            inner, around, rate, marker: PhantomData
+        }
     }
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
-        Vec3dx16::from_tuple((1., 1., 1.,))
+
+    fn transform(&self, x: &Vec3dx16) -> Vec3dx16 {
+        let (along, u, v) = self.around.split(x);
+        let angle = u * self.rate;
+        let (acos, asin) = (angle.cos(), angle.sin());
+
+        self.around.join(along, u * acos - v * asin, u * asin + v * acos)
     }
-}
 
-pub struct Plane {
-    pub height: f32
+    fn lipschitz(&self, x: &Vec3dx16) -> f32x16 {
+        let (_, u, v) = self.around.split(x);
+        1. + (u * u + v * v).sqrt() * self.rate.abs()
+    }
 }
 
-impl World for Plane {
-    fn distance_estimator(&self, x: &Vec3dx16) -> f32x16 {
-        (x.ys - f32x16::splat(self.height)).abs()
+impl <T, TBor> World for Bend<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x), time) / self.lipschitz(x)
     }
-    fn color(&self, x: &Vec3dx16) -> Vec3dx16 {
-        Vec3dx16::from_tuple((1., 1., 1.,))
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (distance, color) = self.inner.borrow().distance_color(&self.transform(x), time);
+        (distance / self.lipschitz(x), color)
     }
-}
 
-pub fn construct_cuboid(at: (f32, f32, f32), dims: (f32, f32, f32)) -> TransT<ScaleT<UnitCube>> {
-    let cu = UnitCube;
-    let scaled = Scale::new(cu, dims);
-    let translated = Translation::new(scaled, at);
-    translated
+    fn bounds(&self) -> Option<Bounds> {
+        // Like a twist, each point only turns around the axis
+        Some(self.inner.borrow().bounds()?.revolve(self.around))
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
 }
 
-pub fn construct_sphere(at: (f32, f32, f32), rad: f32) -> TransT<ScaleT<UnitSphere>> {
-    let sp = UnitSphere;
-    let scaled = Scale::new(sp, (rad, rad, rad));
-    let translated = Translation::new(scaled, at);
-    translated
+pub struct Taper<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    pub around: Axis,
+    // How much the cross section grows per unit along the axis
+    pub rate: f32,
+    marker: PhantomData<TBor>
 }
 
-pub fn raymarch(world: &dyn World, mut poses: Vec3dx16, dirs: Vec3dx16) -> Vec3dx16 {
-    let norms = norm(&dirs);
-    let dirs = dirs
-        / Vec3dx16 {
-            xs: norms,
-            ys: norms,
-            zs: norms,
-        };
+#[allow(dead_code)]
+pub type TaperRef<'a, T> = Taper<&'a T, T>;
+#[allow(dead_code)]
+pub type TaperT<T> = Taper<T, T>;
+
+impl <T, TBor> Taper<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub fn new(inner: T, around: Axis, rate: f32) -> Taper<T, TBor> {
+        Taper {
+            inner, around, rate, marker: PhantomData
+        }
+    }
 
-    let mut res = Vec3dx16::from_tuple((0., 0., 0.));
-    let mut hit = m32x16::splat(false);
+    // Returns the point in the inner world, and the bound on the gradient
+    // of inner(along, u / s, v / s) to divide distances by
+    fn transform(&self, x: &Vec3dx16) -> (Vec3dx16, f32x16) {
+        let (along, u, v) = self.around.split(x);
+        let s = (1. + along * self.rate).max(f32x16::splat(0.05));
+        let (u, v) = (u / s, v / s);
 
-    let mut last_des = f32x16::splat(0.);
+        let shear = (u * u + v * v).sqrt() * self.rate.abs() / s;
+        let lipschitz = (1. / (s * s) + (1. + shear) * (1. + shear)).sqrt();
 
-    for i in 0..MAX_ITERATIONS {
-        if hit.all() {
-            break;
-        }
-        let des = world.distance_estimator(&poses);
-        // Check for collisions (eg. very small distance estimates)
+        (self.around.join(along, u, v), lipschitz)
+    }
+}
 
-        let rays_hit = des.le(f32x16::splat(EPSILON)) & des.lt(last_des);
-        let new_hits = rays_hit & !hit;
+impl <T, TBor> World for Taper<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        let (x_, lipschitz) = self.transform(x);
+        self.inner.borrow().distance_estimator(&x_, time) / lipschitz
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x).0, time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (x_, lipschitz) = self.transform(x);
+        let (distance, color) = self.inner.borrow().distance_color(&x_, time);
+        (distance / lipschitz, color)
+    }
 
-        if new_hits.any() {
-            let fmask = -f32x16::from_cast(new_hits);
+    fn bounds(&self) -> Option<Bounds> {
+        // Scaling the cross section by a factor that only depends on the
+        // position along the axis is largest at the corners of the box
+        let inner = self.inner.borrow().bounds()?;
+        Some(inner.map_corners(|x| {
+            let (along, u, v) = self.around.split(x);
+            let s = (1. + along * self.rate).max(f32x16::splat(0.05));
+            self.around.join(along, u * s, v * s)
+        }))
+    }
 
-            let gray = (des / last_des).min(f32x16::splat(1.));
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
+}
 
-            let colors = world.color(&poses) * Vec3dx16::splat(1. - gray);
+pub struct Displace<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    pub field: Field,
+    pub amplitude: f32,
+    pub frequency: f32,
+    // The displaced distance is no longer a true distance, so steps are
+    // shortened by this factor. `new` picks a conservative value.
+    pub step_scale: f32,
+    marker: PhantomData<TBor>
+}
 
-            res.xs = fmask * colors.xs + (1. - fmask) * res.xs;
-            res.ys = fmask * colors.ys + (1. - fmask) * res.ys;
-            res.zs = fmask * colors.zs + (1. - fmask) * res.zs;
+#[allow(dead_code)]
+pub type DisplaceRef<'a, T> = Displace<&'a T, T>;
+#[allow(dead_code)]
+pub type DisplaceT<T> = Displace<T, T>;
 
-            hit |= new_hits;
+impl <T, TBor> Displace<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub fn new(inner: T, field: Field, amplitude: f32, frequency: f32) -> Displace<T, TBor> {
+        let step_scale = 1. / (1. + amplitude.abs() * frequency.abs() * field.lipschitz());
+        Displace {
+            inner, field, amplitude, frequency, step_scale, marker: PhantomData
         }
-        if i == MAX_ITERATIONS - 1 {
-            let fmask = -f32x16::from_cast(!hit);
+    }
 
-            let gray = (des / last_des).min(f32x16::splat(1.));
+    fn displacement(&self, x: &Vec3dx16) -> f32x16 {
+        let freq = Vec3dx16::from_tuple((self.frequency, self.frequency, self.frequency));
+        self.field.sample(&(x * freq)) * self.amplitude
+    }
+}
 
-            let colors = world.color(&poses) * Vec3dx16::splat(1. - gray);
+impl <T, TBor> World for Displace<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        (self.inner.borrow().distance_estimator(x, time) + self.displacement(x)) * self.step_scale
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(x, time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (distance, color) = self.inner.borrow().distance_color(x, time);
+        ((distance + self.displacement(x)) * self.step_scale, color)
+    }
 
-            res.xs = fmask * colors.xs + (1. - fmask) * res.xs;
-            res.ys = fmask * colors.ys + (1. - fmask) * res.ys;
-            res.zs = fmask * colors.zs + (1. - fmask) * res.zs;
-        }
+    fn bounds(&self) -> Option<Bounds> {
+        let by = self.amplitude.abs() * self.field.bound();
+        Some(self.inner.borrow().bounds()?.expand((by, by, by)))
+    }
 
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
+}
 
-        let move_vec = Vec3dx16 {
-            xs: dirs.xs * des,
-            ys: dirs.ys * des,
-            zs: dirs.zs * des,
-        };
-        poses += move_vec;
-        last_des = des;
+pub struct Round<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    pub radius: f32,
+    marker: PhantomData<TBor>
+}
+
+#[allow(dead_code)]
+pub type RoundRef<'a, T> = Round<&'a T, T>;
+#[allow(dead_code)]
+pub type RoundT<T> = Round<T, T>;
+
+impl <T, TBor> Round<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub fn new(inner: T, radius: f32) -> Round<T, TBor> {
+        Round {
+            inner, radius, marker: PhantomData
+        }
+    }
+}
+
+impl <T, TBor> World for Round<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(x, time) - f32x16::splat(self.radius)
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(x, time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (distance, color) = self.inner.borrow().distance_color(x, time);
+        (distance - f32x16::splat(self.radius), color)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let r = self.radius.max(0.);
+        Some(self.inner.borrow().bounds()?.expand((r, r, r)))
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
+}
+
+pub struct Onion<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    pub thickness: f32,
+    // Each extra layer splits every shell in two, with half the thickness
+    pub layers: u32,
+    marker: PhantomData<TBor>
+}
+
+#[allow(dead_code)]
+pub type OnionRef<'a, T> = Onion<&'a T, T>;
+#[allow(dead_code)]
+pub type OnionT<T> = Onion<T, T>;
+
+impl <T, TBor> Onion<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    #[allow(dead_code)]
+    pub fn new(inner: T, thickness: f32) -> Onion<T, TBor> {
+        Onion::nested(inner, thickness, 1)
+    }
+
+    pub fn nested(inner: T, thickness: f32, layers: u32) -> Onion<T, TBor> {
+        Onion {
+            inner, thickness, layers, marker: PhantomData
+        }
+    }
+
+    fn shell(&self, distance: f32x16) -> f32x16 {
+        let mut res = distance;
+        let mut thickness = self.thickness;
+        for _ in 0..self.layers {
+            res = res.abs() - f32x16::splat(thickness);
+            thickness /= 2.;
+        }
+        res
+    }
+}
+
+impl <T, TBor> World for Onion<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.shell(self.inner.borrow().distance_estimator(x, time))
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(x, time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (distance, color) = self.inner.borrow().distance_color(x, time);
+        (self.shell(distance), color)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        // Each layer moves the outer surface out by its thickness
+        let mut by = 0.;
+        let mut thickness = self.thickness;
+        for _ in 0..self.layers {
+            by += thickness;
+            thickness /= 2.;
+        }
+        Some(self.inner.borrow().bounds()?.expand((by, by, by)))
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
+}
+
+pub struct Elongate<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    // Half of the length added along each axis
+    pub by: (f32, f32, f32),
+    marker: PhantomData<TBor>
+}
+
+#[allow(dead_code)]
+pub type ElongateRef<'a, T> = Elongate<&'a T, T>;
+#[allow(dead_code)]
+pub type ElongateT<T> = Elongate<T, T>;
+
+impl <T, TBor> Elongate<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub fn new(inner: T, by: (f32, f32, f32)) -> Elongate<T, TBor> {
+        Elongate {
+            inner, by, marker: PhantomData
+        }
+    }
+
+    // Collapses the slab |x| <= by onto the origin, which stretches the
+    // inner shape without distorting it
+    fn transform(&self, x: &Vec3dx16) -> Vec3dx16 {
+        let clamp = |v: f32x16, h: f32| v.max(f32x16::splat(-h)).min(f32x16::splat(h));
+        Vec3dx16 {
+            xs: x.xs - clamp(x.xs, self.by.0),
+            ys: x.ys - clamp(x.ys, self.by.1),
+            zs: x.zs - clamp(x.zs, self.by.2),
+        }
+    }
+}
+
+impl <T, TBor> World for Elongate<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x), time)
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        self.inner.borrow().distance_color(&self.transform(x), time)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(self.inner.borrow().bounds()?.expand(self.by))
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
+}
+
+pub struct Mirror<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    // Each plane is (normal, offset), the inner world is only sampled on the
+    // side where dot(x, normal) >= offset
+    pub planes: Vec<((f32, f32, f32), f32)>,
+    marker: PhantomData<TBor>
+}
+
+#[allow(dead_code)]
+pub type MirrorRef<'a, T> = Mirror<&'a T, T>;
+#[allow(dead_code)]
+pub type MirrorT<T> = Mirror<T, T>;
+
+impl <T, TBor> Mirror<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    #[allow(dead_code)]
+    pub fn new(inner: T, normal: (f32, f32, f32), offset: f32) -> Mirror<T, TBor> {
+        let len = (normal.0 * normal.0 + normal.1 * normal.1 + normal.2 * normal.2).sqrt();
+        assert!(len > 0., "Mirror plane needs a non-zero normal");
+        let normal = (normal.0 / len, normal.1 / len, normal.2 / len);
+        Mirror {
+            inner, planes: vec![(normal, offset)], marker: PhantomData
+        }
+    }
+
+    pub fn axes(inner: T, axes: &[Axis]) -> Mirror<T, TBor> {
+        let planes = axes.iter().map(|axis| {
+            let normal = match axis {
+                Axis::X => (1., 0., 0.),
+                Axis::Y => (0., 1., 0.),
+                Axis::Z => (0., 0., 1.),
+            };
+            (normal, 0.)
+        }).collect();
+
+        Mirror {
+            inner, planes, marker: PhantomData
+        }
+    }
+
+    fn transform(&self, x: &Vec3dx16) -> Vec3dx16 {
+        let mut x_ = *x;
+        for &(normal, offset) in &self.planes {
+            let normal = Vec3dx16::from_tuple(normal);
+            let side = (x_.dot(&normal) - f32x16::splat(offset)).min(f32x16::splat(0.));
+            x_ -= normal * Vec3dx16::splat(side * 2.);
+        }
+        x_
+    }
+
+    fn reflect((normal, offset): ((f32, f32, f32), f32), p: (f32, f32, f32)) -> (f32, f32, f32) {
+        let side = 2. * (p.0 * normal.0 + p.1 * normal.1 + p.2 * normal.2 - offset);
+        (p.0 - side * normal.0, p.1 - side * normal.1, p.2 - side * normal.2)
+    }
+}
+
+impl <T, TBor> World for Mirror<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x), time)
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        self.inner.borrow().distance_color(&self.transform(x), time)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        // Every plane adds the mirror image of what the later planes made
+        let mut res = self.inner.borrow().bounds()?;
+        for &plane in self.planes.iter().rev() {
+            let corners: Vec<_> = res.corners().iter().map(|&c| Self::reflect(plane, c)).collect();
+            res = res.union(&Bounds::from_points(&corners));
+        }
+        Some(res)
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
+}
+
+pub struct Kaleidoscope<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    pub around: Axis,
+    pub segments: u32,
+    marker: PhantomData<TBor>
+}
+
+#[allow(dead_code)]
+pub type KaleidoRef<'a, T> = Kaleidoscope<&'a T, T>;
+#[allow(dead_code)]
+pub type KaleidoT<T> = Kaleidoscope<T, T>;
+
+impl <T, TBor> Kaleidoscope<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub fn new(inner: T, around: Axis, segments: u32) -> Kaleidoscope<T, TBor> {
+        assert!(segments > 0, "Kaleidoscope needs at least one segment");
+        Kaleidoscope {
+            inner, around, segments, marker: PhantomData
+        }
+    }
+
+    // Folds the angle around the axis into the wedge between 0 and half a
+    // segment, so only that wedge of the inner world is ever sampled
+    fn transform(&self, x: &Vec3dx16) -> Vec3dx16 {
+        let (along, u, v) = self.around.split(x);
+
+        let sector = f32x16::splat(2. * PI / self.segments as f32);
+        let angle = atan2(v, u);
+        let angle = (angle - sector * floor(angle / sector + 0.5)).abs();
+        let r = (u * u + v * v).sqrt();
+
+        self.around.join(along, r * angle.cos(), r * angle.sin())
+    }
+}
+
+impl <T, TBor> World for Kaleidoscope<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x), time)
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        self.inner.borrow().distance_color(&self.transform(x), time)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        // Folding keeps the distance from the axis
+        Some(self.inner.borrow().bounds()?.revolve(self.around))
+    }
+
+    fn animated(&self) -> bool {
+        self.inner.borrow().animated()
+    }
+}
+
+pub struct Animate<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub inner: T,
+    // Where the inner world has moved to at each lane's time
+    pub path: Box<dyn Fn(f32x16) -> Vec3dx16 + Send + Sync>,
+    marker: PhantomData<TBor>
+}
+
+#[allow(dead_code)]
+pub type AnimateRef<'a, T> = Animate<&'a T, T>;
+#[allow(dead_code)]
+pub type AnimateT<T> = Animate<T, T>;
+
+impl <T, TBor> Animate<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    pub fn new(inner: T, path: Box<dyn Fn(f32x16) -> Vec3dx16 + Send + Sync>) -> Animate<T, TBor> {
+        Animate {
+            inner, path, marker: PhantomData
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn linear(inner: T, velocity: (f32, f32, f32)) -> Animate<T, TBor> {
+        Animate::new(
+            inner,
+            Box::new(move |t| Vec3dx16::from_tuple(velocity) * Vec3dx16::splat(t)),
+        )
+    }
+
+    fn transform(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        x - (self.path)(time)
+    }
+}
+
+impl <T, TBor> World for Animate<T, TBor>
+    where
+        T: Borrow<TBor> + Send + Sync,
+        TBor: World
+{
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.inner.borrow().distance_estimator(&self.transform(x, time), time)
+    }
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.inner.borrow().color(&self.transform(x, time), time)
+    }
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        self.inner.borrow().distance_color(&self.transform(x, time), time)
+    }
+
+    fn animated(&self) -> bool {
+        true
+    }
+}
+
+// The closest of any number of objects. They are kept in a bounding volume
+// hierarchy, so only the ones that could be the closest are evaluated.
+pub struct Union {
+    objects: Bvh,
+}
+
+impl Union {
+    pub fn new(objects: Vec<Box<dyn World>>) -> Union {
+        Union { objects: Bvh::new(objects) }
+    }
+}
+
+impl World for Union {
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        self.objects.distance_estimator(x, time)
+    }
+
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.objects.color(x, time)
+    }
+
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        self.objects.distance_color(x, time)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.objects.bounds()
+    }
+
+    fn animated(&self) -> bool {
+        self.objects.animated()
+    }
+}
+
+pub struct Intersection {
+    pub objects: Vec<Box<dyn World>>
+}
+
+impl World for Intersection {
+    fn distance_estimator(&self, x: &Vec3dx16, time: f32x16) -> f32x16 {
+        let mut res = f32x16::splat(f32::NEG_INFINITY);
+        for obj in &self.objects {
+            res = res.max(obj.distance_estimator(x, time));
+        }
+        res
+    }
+
+    fn color(&self, x: &Vec3dx16, time: f32x16) -> Vec3dx16 {
+        self.objects[0].color(x, time)
+    }
+
+    fn distance_color(&self, x: &Vec3dx16, time: f32x16) -> (f32x16, Vec3dx16) {
+        let (mut res, color) = self.objects[0].distance_color(x, time);
+        for obj in &self.objects[1..] {
+            res = res.max(obj.distance_estimator(x, time));
+        }
+        (res, color)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        // Bounded by any of the objects
+        let mut res: Option<Bounds> = None;
+        for bounds in self.objects.iter().filter_map(|obj| obj.bounds()) {
+            res = Some(res.map_or(bounds, |res| res.intersection(&bounds)));
+        }
+        res
+    }
+
+    fn animated(&self) -> bool {
+        self.objects.iter().any(|obj| obj.animated())
+    }
+}
+
+
+pub struct UnitSphere;
+
+impl World for UnitSphere {
+    fn distance_estimator(&self, x: &Vec3dx16, _time: f32x16) -> f32x16 {
+        norm(x) - f32x16::splat(1.)
+    }
+
+    fn color(&self, _x: &Vec3dx16, _time: f32x16) -> Vec3dx16 {
+        Vec3dx16::from_tuple((1., 1., 1.,))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::new((-1., -1., -1.), (1., 1., 1.)))
+    }
+}
+
+pub struct UnitCube;
+
+impl World for UnitCube {
+    fn distance_estimator(&self, x: &Vec3dx16, _time: f32x16) -> f32x16 {
+        let xs = x.xs.abs() - f32x16::splat(1.);
+        let ys = x.ys.abs() - f32x16::splat(1.);
+        let zs = x.zs.abs() - f32x16::splat(1.);
+        xs.max(ys).max(zs)
+    }
+    fn color(&self, _x: &Vec3dx16, _time: f32x16) -> Vec3dx16 {
+        Vec3dx16::from_tuple((1., 1., 1.,))
+    }
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::new((-1., -1., -1.), (1., 1., 1.)))
+    }
+}
+
+pub struct Plane {
+    pub height: f32
+}
+
+impl World for Plane {
+    fn distance_estimator(&self, x: &Vec3dx16, _time: f32x16) -> f32x16 {
+        (x.ys - f32x16::splat(self.height)).abs()
+    }
+    fn color(&self, _x: &Vec3dx16, _time: f32x16) -> Vec3dx16 {
+        Vec3dx16::from_tuple((1., 1., 1.,))
+    }
+}
+
+pub fn construct_cuboid(at: (f32, f32, f32), dims: (f32, f32, f32)) -> TransT<ScaleT<UnitCube>> {
+    let cu = UnitCube;
+    let scaled = Scale::new(cu, dims);
+    Translation::new(scaled, at)
+}
+
+pub fn construct_sphere(at: (f32, f32, f32), rad: f32) -> TransT<ScaleT<UnitSphere>> {
+    let sp = UnitSphere;
+    let scaled = Scale::new(sp, (rad, rad, rad));
+    Translation::new(scaled, at)
+}
+
+// `count` small spheres on a cubic lattice in front of the camera, colored by
+// where they are, for scenes with a lot of objects
+pub fn construct_sphere_field(count: usize) -> Vec<Box<dyn World>> {
+    let side = (count as f32).cbrt().ceil() as usize;
+    let spacing = 16. / side as f32;
+    let offset = -8. + spacing / 2.;
+
+    (0..count)
+        .map(|i| {
+            let (x, y, z) = (i % side, i / side % side, i / (side * side));
+            let at = (
+                offset + x as f32 * spacing,
+                offset + y as f32 * spacing,
+                6. + z as f32 * spacing,
+            );
+            let sphere = construct_sphere(at, spacing / 4.);
+            let color = (x as f32 / side as f32, y as f32 / side as f32, z as f32 / side as f32);
+            Box::new(Coloring::new(sphere, color)) as Box<dyn World>
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::math::{hash, random};
+
+    const STEP: f32 = 0.04;
+
+    // Points uniformly spread over the box, different in every lane
+    pub(crate) fn random_points(bounds: &Bounds, seed: u32) -> Vec3dx16 {
+        let lanes = (0..16).fold(u32x16::splat(0), |v, lane| v.replace(lane, lane as u32));
+        let seeds = hash(lanes + seed * 16 + 1);
+        let coord = |k: u32, min: f32, max: f32| random(seeds + k) * (max - min) + min;
+        Vec3dx16 {
+            xs: coord(0, bounds.min.0, bounds.max.0),
+            ys: coord(1, bounds.min.1, bounds.max.1),
+            zs: coord(2, bounds.min.2, bounds.max.2),
+        }
+    }
+
+    // The points of a grid over `region` where the distance is at most
+    // `level` but next to a point where it's more, a sampled version of the
+    // surface when `level` is zero
+    fn surface(world: &dyn World, region: &Bounds, level: f32) -> Vec<(f32, f32, f32)> {
+        let count = |min: f32, max: f32| ((max - min) / STEP) as usize + 1;
+        let (nx, ny, nz) = (
+            count(region.min.0, region.max.0),
+            count(region.min.1, region.max.1),
+            count(region.min.2, region.max.2),
+        );
+        let at = |i: usize, j: usize, k: usize| (
+            region.min.0 + i as f32 * STEP,
+            region.min.1 + j as f32 * STEP,
+            region.min.2 + k as f32 * STEP,
+        );
+
+        let grid: Vec<_> = (0..nz)
+            .flat_map(|k| (0..ny).flat_map(move |j| (0..nx).map(move |i| (i, j, k))))
+            .collect();
+        let mut inside = Vec::with_capacity(grid.len());
+        for chunk in grid.chunks(16) {
+            let mut x = Vec3dx16::default();
+            for (lane, &(i, j, k)) in chunk.iter().enumerate() {
+                x.replace(lane, at(i, j, k));
+            }
+            let distances = world.distance_estimator(&x, f32x16::splat(0.));
+            inside.extend((0..chunk.len()).map(|lane| distances.extract(lane) <= level));
+        }
+
+        // Outside the grid counts as outside the world
+        let is_inside = |i: isize, j: isize, k: isize| {
+            i >= 0 && j >= 0 && k >= 0 && (i as usize) < nx && (j as usize) < ny && (k as usize) < nz
+                && inside[(k as usize * ny + j as usize) * nx + i as usize]
+        };
+        let neighbors = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)];
+
+        grid.iter()
+            .filter(|&&(i, j, k)| {
+                let (i, j, k) = (i as isize, j as isize, k as isize);
+                is_inside(i, j, k)
+                    && neighbors.iter().any(|&(di, dj, dk)| !is_inside(i + di, j + dj, k + dk))
+            })
+            .map(|&(i, j, k)| at(i, j, k))
+            .collect()
+    }
+
+    // Checks that the distance estimate outside the world is never more than
+    // the distance to the sampled surface, which is at least the true
+    // distance, and that the bounds contain the surface. The surface has to
+    // be inside `region`.
+    pub(crate) fn assert_conservative(world: &dyn World, region: Bounds) {
+        assert_conservative_near(world, region, 0.);
+    }
+
+    // The same for the surface where the distance is `level`, for worlds
+    // like IFS fractals that are made of points and never reach zero. Steps
+    // are still safe if the estimate is never more than `level` plus the
+    // distance to it.
+    pub(crate) fn assert_conservative_near(world: &dyn World, region: Bounds, level: f32) {
+        let surface = surface(world, &region, level);
+        assert!(!surface.is_empty(), "No surface in {:?}", region);
+
+        if let Some(bounds) = world.bounds() {
+            let bounds = bounds.expand((STEP, STEP, STEP));
+            for p in &surface {
+                assert!(
+                    bounds.min.0 <= p.0 && p.0 <= bounds.max.0
+                        && bounds.min.1 <= p.1 && p.1 <= bounds.max.1
+                        && bounds.min.2 <= p.2 && p.2 <= bounds.max.2,
+                    "{:?} is outside of {:?}", p, bounds
+                );
+            }
+        }
+
+        let around = region.expand((1., 1., 1.));
+        for seed in 0..32 {
+            let x = random_points(&around, seed);
+            let distances = world.distance_estimator(&x, f32x16::splat(0.));
+            for lane in 0..16 {
+                let p = x.extract(lane);
+                let truth = surface.iter()
+                    .map(|s| ((p.0 - s.0).powi(2) + (p.1 - s.1).powi(2) + (p.2 - s.2).powi(2)).sqrt())
+                    .fold(f32::INFINITY, f32::min);
+                let distance = distances.extract(lane);
+                assert!(distance <= level + truth + 1e-4, "{} > {} at {:?}", distance, level + truth, p);
+            }
+        }
+    }
+
+    pub(crate) fn region(size: f32) -> Bounds {
+        Bounds::new((-size, -size, -size), (size, size, size))
+    }
+
+    #[test]
+    fn mirror_is_conservative() {
+        let sphere = Translation::new(Scale::new(UnitSphere, (0.6, 0.6, 0.6)), (1., 0.5, 0.2));
+        assert_conservative(&Mirror::new(sphere, (1., 1., 0.), 0.3), region(2.5));
+
+        let cube = Translation::new(Scale::new(UnitCube, (0.4, 0.3, 0.5)), (0.8, 0.6, 0.7));
+        assert_conservative(&Mirror::axes(cube, &[Axis::X, Axis::Z]), region(2.));
+    }
+
+    #[test]
+    fn kaleidoscope_is_conservative() {
+        let cube = Translation::new(Scale::new(UnitCube, (0.3, 0.5, 0.3)), (0.4, 0.2, 1.2));
+        assert_conservative(&Kaleidoscope::new(cube, Axis::Y, 5), region(2.));
+    }
+
+    #[test]
+    fn twist_is_conservative() {
+        let cube = Scale::new(UnitCube, (0.8, 1.2, 0.4));
+        assert_conservative(&Twist::new(cube, Axis::Y, 1.5), region(2.));
+    }
+
+    #[test]
+    fn bend_is_conservative() {
+        let cube = Scale::new(UnitCube, (1.5, 0.3, 0.4));
+        assert_conservative(&Bend::new(cube, Axis::Z, 0.6), region(2.));
+    }
+
+    #[test]
+    fn taper_is_conservative() {
+        let cube = Scale::new(UnitCube, (0.6, 1.2, 0.6));
+        assert_conservative(&Taper::new(cube, Axis::Y, 0.5), region(2.));
+    }
+
+    #[test]
+    fn round_is_conservative() {
+        let cube = Scale::new(UnitCube, (0.7, 0.7, 0.7));
+        assert_conservative(&Round::new(cube, 0.3), region(2.));
+        assert_conservative(&Round::new(Elongate::new(UnitSphere, (0.5, 0., 0.)), 0.2), region(2.));
+    }
+
+    #[test]
+    fn onion_is_conservative() {
+        assert_conservative(&Onion::new(UnitSphere, 0.15), region(2.));
+        assert_conservative(&Onion::nested(UnitCube, 0.2, 2), region(2.));
+    }
+
+    #[test]
+    fn elongate_is_conservative() {
+        let sphere = Scale::new(UnitSphere, (0.5, 0.5, 0.5));
+        assert_conservative(&Elongate::new(sphere, (0.6, 0., 0.3)), region(2.));
+    }
+
+    #[test]
+    fn transform_is_conservative() {
+        let rotation = Quat::from_axis_angle((1., 2., -0.5), 0.7);
+        assert_conservative(&Transform::from_parts(UnitCube, rotation, (0.3, -0.2, 0.1), 0.8), region(2.));
+
+        let shear = Mat3x4 {
+            rows: [
+                [0.6, 0.3, 0., 0.2],
+                [0., 0.5, 0.2, -0.1],
+                [0.1, 0., 1.2, 0.],
+            ],
+        };
+        assert_conservative(&Transform::new(UnitSphere, shear), region(2.));
+    }
+
+    #[test]
+    fn displace_is_conservative() {
+        assert_conservative(&Displace::new(UnitSphere, Field::Sine, 0.2, 4.), region(2.));
+        assert_conservative(&Displace::new(UnitSphere, Field::Perlin, 0.3, 3.), region(2.));
+
+        let fbm = Field::Fbm { octaves: 3, lacunarity: 2., gain: 0.5 };
+        assert_conservative(&Displace::new(UnitCube, fbm, 0.2, 2.), region(2.));
+    }
+
+    #[test]
+    fn animate_moves() {
+        let moving = AnimateT::linear(UnitSphere, (1., 0., -0.5));
+        assert!(moving.animated());
+
+        let x = random_points(&region(2.), 0);
+        let zeros = f32x16::splat(0.);
+        let at = |time: f32| moving.distance_estimator(&x, f32x16::splat(time));
+        let moved = |by: f32| {
+            UnitSphere.distance_estimator(&(x - Vec3dx16::from_tuple((by, 0., -0.5 * by))), zeros)
+        };
+
+        // The sphere starts at the origin and has moved 2 seconds along its
+        // path at time 2
+        let (before, after) = (at(0.), at(2.));
+        for i in 0..16 {
+            assert!((before.extract(i) - moved(0.).extract(i)).abs() < 1e-5);
+            assert!((after.extract(i) - moved(2.).extract(i)).abs() < 1e-5);
+        }
+        assert!((after - before).abs().gt(f32x16::splat(0.1)).any());
+
+        // Each lane is where it is at its own time
+        let times = (0..16).fold(zeros, |t, lane| t.replace(lane, lane as f32 * 0.2));
+        let each = moving.distance_estimator(&x, times);
+        for i in 0..16 {
+            assert_eq!(each.extract(i), at(i as f32 * 0.2).extract(i));
+        }
     }
-    res
 }